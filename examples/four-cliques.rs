@@ -45,8 +45,8 @@ fn main () {
             // dQdE := dE x A x B x C x D x F
             // dQdF := dF x A x B x C x D x E
 
-            let forward = IndexStream::from(|k| k as u64, &Vec::new().to_stream(builder), &dG);
-            let reverse = IndexStream::from(|k| k as u64, &Vec::new().to_stream(builder), &dG.map(|((src,dst),wgt)| ((dst,src),wgt)));
+            let forward = IndexStream::from(partition::mix, &Vec::new().to_stream(builder), &dG);
+            let reverse = IndexStream::from(partition::mix, &Vec::new().to_stream(builder), &dG.map(|((src,dst),wgt)| ((dst,src),wgt)));
 
             // We then pick an ordering of attributes for each derivative:
             // dQdA: we start with dA(a1, a2) and extend to a3 and then to a4. So there will be 2 extensions:
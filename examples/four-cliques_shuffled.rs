@@ -38,8 +38,8 @@ fn main () {
             let (graph_input, graph) = builder.new_input::<(u32, u32)>();
             let (query_input, query) = builder.new_input::<((u32, u32), i32)>();
             
-            let forward = IndexStream::from(|k| k as u64, &graph, &query);
-            let reverse = IndexStream::from(|k| k as u64, &graph.map(|(src,dst)| (dst,src)), &query.map(|((src,dst),wgt)| ((dst,src),wgt)));
+            let forward = IndexStream::from(partition::mix, &graph, &query);
+            let reverse = IndexStream::from(partition::mix, &graph.map(|(src,dst)| (dst,src)), &query.map(|((src,dst),wgt)| ((dst,src),wgt)));
 
             // construct the four_cliques dataflow subgraph.
             let cliques = cliques_4(&query, &forward, &reverse);
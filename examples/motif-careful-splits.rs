@@ -3,7 +3,6 @@ extern crate alg3_dynamic;
 
 use std::sync::{Arc, Mutex};
 use std::io::BufReader;
-use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -34,10 +33,14 @@ fn main () {
         let mut motif = vec![];
         let query_size: usize = std::env::args().nth(1).unwrap().parse().unwrap();
         for query in 0 .. query_size {
-            let attr1: usize = std::env::args().nth(2 * (query + 1) + 0).unwrap().parse().unwrap();
+            let attr1: usize = std::env::args().nth(2 * (query + 1)).unwrap().parse().unwrap();
             let attr2: usize = std::env::args().nth(2 * (query + 1) + 1).unwrap().parse().unwrap();
             motif.push((attr1, attr2));
         }
+        let motif = match motif::Motif::new(&motif) {
+            Ok(motif) => motif,
+            Err(error) => panic!("invalid motif: {}", error),
+        };
 
         // load fragment of input graph into memory to avoid io while running.
         let filename = std::env::args().nth(2 * (query_size) + 2).unwrap();
@@ -47,7 +50,7 @@ fn main () {
         let query_batch: usize = std::env::args().nth(2 * (query_size) + 6).unwrap().parse().unwrap();
 
         if index==0 {
-            println!("motif:\t{:?}", motif);
+            println!("motif:\t{:?}", motif.edges());
             println!("filename:\t{:?} , {:?}", filename, number_files);
         }
 
@@ -60,7 +63,7 @@ fn main () {
             let (delta_input, delta) = builder.new_input::<((Node, Node), i32)>();
             
             // // create indices and handles from the initial edges plus updates.
-            let (graph_index, handles) = motif::GraphStreamIndex::from_separately(graph1, graph2, delta, |k| k as u64, |k| k as u64);
+            let (graph_index, handles) = motif::GraphStreamIndex::from_separately(graph1, graph2, delta, partition::mix, partition::mix);
 
             // construct the motif dataflow subgraph.
             let motifs = graph_index.track_motif(&motif);
@@ -85,31 +88,29 @@ fn main () {
 
         // start the experiment!
         let start = ::std::time::Instant::now();
-        let mut remaining = pre_load;
-        let max_vertex = pre_load as Node;
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         input_delta.advance_to(prev_time.inner + 1);
 
 
     if number_files == 1 {
         // Open the path in read-only mode, returns `io::Result<File>`
-        let mut lines = match File::open(&Path::new(&filename)) {
+        let mut lines = match File::open(Path::new(&filename)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&filename).display(),
-                       Error::description(&why))
+                       why)
             },
         };
         // load up the graph, using the first `limit` lines in the file.
         for (counter, line) in lines.by_ref().take(pre_load).enumerate() {
             // each worker is responsible for a fraction of the queries
             if counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                   let mut elements = good_line[..].split_whitespace();
-                  let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                  let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                  let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                  let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                   input_graph1.send((src, dst));
                 }
             }
@@ -121,23 +122,23 @@ fn main () {
         //
         // REPEAT ABOVE
         // Open the path in read-only mode, returns `io::Result<File>`
-        let mut lines = match File::open(&Path::new(&filename)) {
+        let mut lines = match File::open(Path::new(&filename)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&filename).display(),
-                       Error::description(&why))
+                       why)
             },
         };
         // load up the graph, using the first `limit` lines in the file.
         for (counter, line) in lines.by_ref().take(pre_load).enumerate() {
             // each worker is responsible for a fraction of the queries
             if counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                     input_graph2.send((src, dst));
                 }
             }
@@ -179,27 +180,23 @@ fn main () {
     
         println!("worker{:?} --> filename: {:?} {:?}", index,p, p_str);
  
-          let mut lines = match File::open(&Path::new(&p_str)) {
+          let mut lines = match File::open(Path::new(&p_str)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&p_str).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
-          remaining = 0;
-
         // load up all lines in the file.
-        for (counter, line) in lines.by_ref().enumerate() {
-          // count edges 
-          remaining = remaining +1 ;
+        for line in lines.by_ref() {
            // each worker should load all available edges. Note that each partition is handled by one worker only.
-           let good_line = line.ok().expect("EXCEPTION: read error");
-           if !good_line.starts_with('#') && good_line.len() > 0 {
+           let good_line = line.expect("EXCEPTION: read error");
+           if !good_line.starts_with('#') && !good_line.is_empty() {
                let mut elements = good_line[..].split_whitespace();
-               let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-               let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+               let src: Node = elements.next().unwrap().parse().expect("malformed src");
+               let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                input_graph1.send((src, dst)); // send each edge to its responsible worker;
             }
         }
@@ -241,36 +238,34 @@ fn main () {
   
         println!("worker{:?} --> filename: {:?} {:?}", index,p, p_str);
  
-        let mut lines = match File::open(&Path::new(&p_str)) {
+        let _lines = match File::open(Path::new(&p_str)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&p_str).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
-        remaining = 0;
-
 
         // Open the path in read-only mode, returns `io::Result<File>`
-        let mut lines = match File::open(&Path::new(&p_str)) {
+        let mut lines = match File::open(Path::new(&p_str)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&p_str).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
         // load up the graph, using the first `limit` lines in the file.
-        for (counter, line) in lines.by_ref().enumerate() {
+        for line in lines.by_ref() {
             // each worker is responsible for a fraction of the queries
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                    let mut elements = good_line[..].split_whitespace();
-                   let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                   let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                   let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                   let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                    input_graph2.send((src, dst));
                 }
         }
@@ -296,22 +291,22 @@ fn main () {
         // loop { }
 
         // merge all of the indices the worker maintains.
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         handles.merge_to(&prev_time);
 
         // synchronize with other workers before reporting indices merged.
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         // input_graph.advance_to(prev_time.inner + 1);
         input_delta.advance_to(prev_time.inner + 1);
         root.step_while(|| probe.less_than(input_delta.time()));
         println!("{:?}\t[worker {}]\tindices merged", start.elapsed(), index);
 
-        let lines = match File::open(&Path::new(&query_filename)) {
+        let lines = match File::open(Path::new(&query_filename)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&query_filename).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
@@ -323,11 +318,11 @@ fn main () {
 
             // each worker is responsible for a fraction of the queries
             if query_counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                     // input_delta.send(((src, dst), 1));
                     batch.push((src, dst));
                 }
@@ -339,20 +334,20 @@ fn main () {
                 batch = Vec::new();
             }
         }
-        if batch.len() > 0 { batches.push(batch); }
+        if !batch.is_empty() { batches.push(batch); }
 
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         input_delta.advance_to(prev_time.inner + 1);
         root.step_while(|| probe.less_than(input_delta.time()));
         println!("{:?}\t[worker {}]\tquery input batches parsed (number: {}).", start.elapsed(), index, batches.len());
 
-        for round in 0 .. batches.len() {
+        for (round, batch) in batches.iter_mut().enumerate() {
 
-            for (src, dst) in batches[round].drain(..) {
+            for (src, dst) in batch.drain(..) {
                 input_delta.send(((src, dst), 1));
             }
 
-            let prev_time = input_delta.time().clone();
+            let prev_time = *input_delta.time();
             input_delta.advance_to(prev_time.inner + 1);
             root.step_while(|| probe.less_than(input_delta.time()));
             handles.merge_to(&prev_time);
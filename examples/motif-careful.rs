@@ -3,7 +3,6 @@ extern crate alg3_dynamic;
 
 use std::sync::{Arc, Mutex};
 use std::io::BufReader;
-use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -34,17 +33,21 @@ fn main () {
         let mut motif = vec![];
         let query_size: usize = std::env::args().nth(1).unwrap().parse().unwrap();
         for query in 0 .. query_size {
-            let attr1: usize = std::env::args().nth(2 * (query + 1) + 0).unwrap().parse().unwrap();
+            let attr1: usize = std::env::args().nth(2 * (query + 1)).unwrap().parse().unwrap();
             let attr2: usize = std::env::args().nth(2 * (query + 1) + 1).unwrap().parse().unwrap();
             motif.push((attr1, attr2));
         }
+        let motif = match motif::Motif::new(&motif) {
+            Ok(motif) => motif,
+            Err(error) => panic!("invalid motif: {}", error),
+        };
 
         // load fragment of input graph into memory to avoid io while running.
         let filename = std::env::args().nth(2 * (query_size) + 2).unwrap();
         let pre_load = std::env::args().nth(2 * (query_size) + 3).unwrap().parse().unwrap();
         let query_batch: usize = std::env::args().nth(2 * (query_size) + 4).unwrap().parse().unwrap();
 
-        println!("motif:\t{:?}", motif);
+        println!("motif:\t{:?}", motif.edges());
         println!("filename:\t{:?}", filename);
 
         // handles to input and probe, but also both indices so we can compact them.
@@ -56,7 +59,7 @@ fn main () {
             let (delta_input, delta) = builder.new_input::<((Node, Node), i32)>();
             
             // create indices and handles from the initial edges plus updates.
-            let (graph_index, handles) = motif::GraphStreamIndex::from_separately(graph1, graph2, delta, |k| k as u64, |k| k as u64);
+            let (graph_index, handles) = motif::GraphStreamIndex::from_separately(graph1, graph2, delta, partition::mix, partition::mix);
 
             // construct the motif dataflow subgraph.
             let motifs = graph_index.track_motif(&motif);
@@ -83,17 +86,17 @@ fn main () {
         let start = ::std::time::Instant::now();
 
 
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         input_delta.advance_to(prev_time.inner + 1);
 
 
         // Open the path in read-only mode, returns `io::Result<File>`
-        let mut lines = match File::open(&Path::new(&filename)) {
+        let mut lines = match File::open(Path::new(&filename)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&filename).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
@@ -101,11 +104,11 @@ fn main () {
         for (counter, line) in lines.by_ref().take(pre_load).enumerate() {
             // each worker is responsible for a fraction of the queries
             if counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                     input_graph1.send((src, dst));
                 }
             }
@@ -122,12 +125,12 @@ fn main () {
 
 
         // Open the path in read-only mode, returns `io::Result<File>`
-        let mut lines = match File::open(&Path::new(&filename)) {
+        let mut lines = match File::open(Path::new(&filename)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&filename).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
@@ -135,11 +138,11 @@ fn main () {
         for (counter, line) in lines.by_ref().take(pre_load).enumerate() {
             // each worker is responsible for a fraction of the queries
             if counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                     input_graph2.send((src, dst));
                 }
             }
@@ -161,11 +164,11 @@ fn main () {
         // loop { }
 
         // merge all of the indices the worker maintains.
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         handles.merge_to(&prev_time);
 
         // synchronize with other workers before reporting indices merged.
-        let prev_time = input_delta.time().clone();
+        let prev_time = *input_delta.time();
         // input_graph.advance_to(prev_time.inner + 1);
         input_delta.advance_to(prev_time.inner + 1);
         root.step_while(|| probe.less_than(input_delta.time()));
@@ -176,18 +179,18 @@ fn main () {
 
             // each worker is responsible for a fraction of the queries
             if query_counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                     input_delta.send(((src, dst), 1));
                 }
             }
 
             // synchronize and merge indices.
             if query_counter % query_batch == (query_batch - 1) {
-                let prev_time = input_delta.time().clone();
+                let prev_time = *input_delta.time();
                 // input_graph.advance_to(prev_time.inner + 1);
                 input_delta.advance_to(prev_time.inner + 1);
                 root.step_while(|| probe.less_than(input_delta.time()));
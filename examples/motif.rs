@@ -1,19 +1,25 @@
 extern crate timely;
 extern crate alg3_dynamic;
 
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::io::BufReader;
-use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
+use timely::dataflow::InputHandle;
 use timely::dataflow::operators::*;
 
 use alg3_dynamic::*;
 
 type Node = u32;
 
+// the input and cancellation of a query attached to the live indices.
+type Attached = (InputHandle<Node, ((Node, Node), i32)>, motif::Cancel);
+
 fn main () {
 
     let start = ::std::time::Instant::now();
@@ -23,6 +29,73 @@ fn main () {
 
     let inspect = ::std::env::args().find(|x| x == "inspect").is_some();
 
+    // optionally, "drain" drains later stages of the dataflow before proposing more extensions.
+    let draining = ::std::env::args().find(|x| x == "drain").is_some();
+
+    // optionally, "fanout" reports the work of each stage of the dataflow for each batch.
+    let fanout = ::std::env::args().find(|x| x == "fanout").is_some();
+
+    // optionally, "audit=<slack>" reports stages proposing more than `slack` times the AGM bound.
+    let audit: Option<f64> = ::std::env::args().find(|x| x.starts_with("audit=")).map(|x| x["audit=".len()..].parse().expect("malformed audit"));
+
+    // optionally, "memory=<bytes>" bounds the bytes of each index, and stops once updates are refused.
+    let memory: Option<usize> = ::std::env::args().find(|x| x.starts_with("memory=")).map(|x| x["memory=".len()..].parse().expect("malformed memory"));
+
+    // optionally, "admit=<batches>[:<records>]" lets that many batches of updates, and at most that many 
+    // updates at each worker, be in flight at once.
+    let admit: Option<(usize, Option<usize>)> = ::std::env::args().find(|x| x.starts_with("admit=")).map(|x| {
+        let mut parts = x["admit=".len()..].split(':');
+        let batches: usize = parts.next().unwrap().parse().expect("malformed admit");
+        let records: Option<usize> = parts.next().map(|x| x.parse().expect("malformed admit"));
+        (batches, records)
+    });
+
+    // optionally, "guard=<prefixes>:<abort|skip|count>" limits the prefixes per timestamp.
+    let guard = ::std::env::args().find(|x| x.starts_with("guard=")).map(|x| {
+        let mut parts = x["guard=".len()..].split(':');
+        let prefixes: usize = parts.next().unwrap().parse().expect("malformed guard");
+        let action = match parts.next() {
+            Some("abort") => GuardAction::Abort,
+            Some("skip") => GuardAction::Skip,
+            Some("count") => GuardAction::CountOnly,
+            other => panic!("unknown guard action: {:?}", other),
+        };
+        (prefixes, action)
+    });
+
+    // optionally, "explain" prints the plan of each relation's dataflow, and "explain=dot" renders it in DOT.
+    let explain = ::std::env::args().find(|x| x == "explain" || x.starts_with("explain=")).map(|x| x["explain".len()..].to_owned());
+
+    // optionally, "order=<relation>:<attr>,<attr>,.." binds the attributes of a relation's dataflow in 
+    // that order, and "pin=<relation>:<attr>:<proposer>" proposes an attribute from one relation.
+    let orders = ::std::env::args().filter(|x| x.starts_with("order=")).map(|x| {
+        let mut parts = x["order=".len()..].split(':');
+        let relation: usize = parts.next().unwrap().parse().expect("malformed order");
+        let order: Vec<usize> = parts.next().expect("malformed order").split(',').map(|a| a.parse().expect("malformed order")).collect();
+        (relation, order)
+    }).collect::<Vec<_>>();
+    let pins = ::std::env::args().filter(|x| x.starts_with("pin=")).map(|x| {
+        let parts: Vec<usize> = x["pin=".len()..].split(':').map(|a| a.parse().expect("malformed pin")).collect();
+        assert_eq!(parts.len(), 3, "malformed pin");
+        (parts[0], parts[1], parts[2])
+    }).collect::<Vec<_>>();
+
+    // optionally, "attach=<batches>" attaches a second query for the motif after that many batches,
+    // and "retire=<batches>" cancels it after that many batches.
+    let attach: Option<usize> = ::std::env::args().find(|x| x.starts_with("attach=")).map(|x| x["attach=".len()..].parse().expect("malformed attach"));
+    let retire: Option<usize> = ::std::env::args().find(|x| x.starts_with("retire=")).map(|x| x["retire=".len()..].parse().expect("malformed retire"));
+
+    // optionally, "point=<src>,<dst>" reports the instances binding the first relation to that edge, 
+    // once the indices are merged.
+    let point: Option<(Node, Node)> = ::std::env::args().find(|x| x.starts_with("point=")).map(|x| {
+        let nodes: Vec<Node> = x["point=".len()..].split(',').map(|n| n.parse().expect("malformed point")).collect();
+        assert_eq!(nodes.len(), 2, "malformed point");
+        (nodes[0], nodes[1])
+    });
+
+    // optionally, "partition=<mix|modulo|range|degree>" places nodes other than by a well-mixed hash.
+    let partition = ::std::env::args().find(|x| x.starts_with("partition=")).map(|x| x["partition=".len()..].to_owned());
+
     timely::execute_from_args(std::env::args(), move |root| {
 
         let send = send.clone();
@@ -34,31 +107,90 @@ fn main () {
         let mut motif = vec![];
         let query_size: usize = std::env::args().nth(1).unwrap().parse().unwrap();
         for query in 0 .. query_size {
-            let attr1: usize = std::env::args().nth(2 * (query + 1) + 0).unwrap().parse().unwrap();
+            let attr1: usize = std::env::args().nth(2 * (query + 1)).unwrap().parse().unwrap();
             let attr2: usize = std::env::args().nth(2 * (query + 1) + 1).unwrap().parse().unwrap();
             motif.push((attr1, attr2));
         }
+        let motif = match motif::Motif::new(&motif) {
+            Ok(motif) => motif,
+            Err(error) => panic!("invalid motif: {}", error),
+        };
 
         // load fragment of input graph into memory to avoid io while running.
         let filename = std::env::args().nth(2 * (query_size) + 2).unwrap();
         let pre_load = std::env::args().nth(2 * (query_size) + 3).unwrap().parse().unwrap();
         let query_batch: usize = std::env::args().nth(2 * (query_size) + 4).unwrap().parse().unwrap();
 
-        println!("motif:\t{:?}", motif);
+        println!("motif:\t{:?}", motif.edges());
         println!("filename:\t{:?}", filename);
 
+        let mut overrides = motif::PlanOverrides::new();
+        for &(relation, ref order) in orders.iter() { overrides = overrides.order(relation, order.clone()); }
+        for &(relation, attribute, proposer) in pins.iter() { overrides = overrides.pin(relation, attribute, proposer); }
+        let plans = match motif::explain_with(&motif, &overrides) {
+            Ok(plans) => plans,
+            Err(error) => panic!("invalid plan overrides: {}", error),
+        };
+
+        if index == 0 {
+            for warning in motif.warnings() {
+                println!("warning:\t{}", warning);
+            }
+            match explain.as_ref().map(|x| &x[..]) {
+                None => { },
+                Some("") => { for plan in plans.iter() { print!("{}", plan); } },
+                Some("=dot") => { print!("{}", motif::explain_dot(&plans)); },
+                Some(other) => panic!("unknown explain format: {:?}", other),
+            }
+        }
+
+        let partitioner: Rc<dyn Fn(Node)->u64> = match partition.as_ref().map(|x| &x[..]) {
+            None | Some("mix") => Rc::new(partition::mix),
+            Some("modulo") => Rc::new(|k| k as u64),
+            Some("range") => {
+                let nodes = degrees(&filename).len() as u64;
+                let range = partition::range(partition::even_bounds(nodes, peers));
+                Rc::new(move |k| range(k as u64))
+            },
+            Some("degree") => {
+                // balance the highest degree nodes explicitly; the rest are well mixed.
+                let mut degrees = degrees(&filename).into_iter().enumerate().map(|(node, degree)| (node as Node, degree)).collect::<Vec<_>>();
+                degrees.sort_by_key(|x| ::std::cmp::Reverse(x.1));
+                degrees.truncate(1 << 16);
+                Rc::new(partition::balanced(&degrees, peers))
+            },
+            Some(other) => panic!("unknown partition: {:?}", other),
+        };
+
+        let guard = guard.map(|(prefixes, action)| Guard::new(prefixes, action));
+        let events = guard.clone();
+        let counted = fanout || audit.is_some();
+        let stages = if counted { Some(Fanout::new()) } else { None };
+        let counts = Rc::new(RefCell::new(Vec::new()));
+        let stage_counts = counts.clone();
+        let audit = audit.map(|slack| agm::Audit::new(0, motif.edges(), slack));
+        let audited = audit.is_some();
+        let sizes = Rc::new(RefCell::new(HashMap::new()));
+        let total_sizes = sizes.clone();
+        let attached_motif = motif.clone();
+        let (attributes, first) = (motif.attributes(), motif.edges()[0]);
+        let attached_send = send.clone();
+
         // handles to input and probe, but also both indices so we can compact them.
-        let (mut input_graph, mut input_delta, probe, handles) = root.dataflow::<Node,_,_>(move |builder| {
+        let (mut input_graph, mut input_delta, mut input_points, mut input_sizes, mut input_loads, probe, handles, shared) = root.dataflow::<Node,_,_>(move |builder| {
 
             // inputs for initial edges and changes to the edge set, respectively.
             let (graph_input, graph) = builder.new_input::<(Node, Node)>();
             let (delta_input, delta) = builder.new_input::<((Node, Node), i32)>();
             
-            // create indices and handles from the initial edges plus updates.
-            let (graph_index, handles) = motif::GraphStreamIndex::from(graph, delta, |k| k as u64, |k| k as u64);
+            // create co-partitioned indices and handles from the initial edges plus updates.
+            let (mut graph_index, handles) = motif::GraphStreamIndex::from_copartitioned(graph, delta, move |k| partitioner(k));
+            graph_index.set_draining(draining);
+            graph_index.set_guard(guard);
+            graph_index.set_fanout(stages);
 
             // construct the motif dataflow subgraph.
-            let motifs = graph_index.track_motif(&motif);
+            let motifs = graph_index.track_motif_with(&motif, &overrides).expect("overrides were checked");
 
             // if "inspect", report motif counts.
             if inspect {
@@ -72,19 +204,64 @@ fn main () {
                     });
             }
 
-            (graph_input, delta_input, motifs.probe(), handles)
+            // if "inspect", report the keys and values of each worker's forward index at worker zero.
+            let loads_input = if inspect {
+                let (loads_input, loads) = builder.new_input::<(usize, usize, usize)>();
+                partition::report(&loads)
+                    .inspect(|loads| { for load in loads.iter() { println!("forward index load: {:?}", load); } });
+                Some(loads_input)
+            }
+            else { None };
+
+            // if "point", report the instances answering each point query.
+            let points_input = point.map(|_| {
+                let (points_input, points) = builder.new_input::<(u64, Vec<Option<Node>>)>();
+                let (answers, rejected) = graph_index
+                    .point_queries(&motif, &points)
+                    .expect("point queries require a connected motif");
+                answers.inspect(|&(id, ref instance)| println!("point query {}: {:?}", id, instance));
+                rejected.inspect(|&(id, _)| println!("point query {}: rejected, as it binds no attribute", id));
+                points_input
+            });
+
+            // if counting stages, collect the counts of each timestamp as it completes.
+            let mut probe = motifs.probe();
+            if counted {
+                graph_index
+                    .stage_counts()
+                    .inspect_batch(move |time, batch| {
+                        let mut counts = stage_counts.borrow_mut();
+                        counts.extend(batch.iter().map(|&(stage, ref count)| (*time, stage, count.clone())));
+                    })
+                    .probe_with(&mut probe);
+            }
+
+            // if auditing, sum the edges each worker's forward index holds at each timestamp.
+            let sizes_input = if audited {
+                let (sizes_input, sizes) = builder.new_input::<u64>();
+                sizes
+                    .broadcast()
+                    .accumulate(0, |sum, data| { for &size in data.iter() { *sum += size; } })
+                    .inspect_batch(move |time, batch| { total_sizes.borrow_mut().insert(*time, batch[0]); })
+                    .probe_with(&mut probe);
+                Some(sizes_input)
+            }
+            else { None };
+
+            (graph_input, delta_input, points_input, sizes_input, loads_input, probe, handles, graph_index.share())
         });
+        handles.set_memory_limit(memory);
 
         // start the experiment!
         let start = ::std::time::Instant::now();
 
         // Open the path in read-only mode, returns `io::Result<File>`
-        let mut lines = match File::open(&Path::new(&filename)) {
+        let mut lines = match File::open(Path::new(&filename)) {
             Ok(file) => BufReader::new(file).lines(),
             Err(why) => {
                 panic!("EXCEPTION: couldn't open {}: {}",
                        Path::new(&filename).display(),
-                       Error::description(&why))
+                       why)
             },
         };
 
@@ -92,61 +269,211 @@ fn main () {
         for (counter, line) in lines.by_ref().take(pre_load).enumerate() {
             // each worker is responsible for a fraction of the queries
             if counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
                     input_graph.send((src, dst));
                 }
             }
         }
 
         // synchronize with other workers before reporting data loaded.
-        let prev_time = input_graph.time().clone();
+        let prev_time = *input_graph.time();
         input_graph.advance_to(prev_time.inner + 1);
         input_delta.advance_to(prev_time.inner + 1);
+        if let Some(ref mut input) = input_sizes { input.advance_to(prev_time.inner + 1); }
         root.step_while(|| probe.less_than(input_graph.time()));
         println!("{:?}\t[worker {}]\tdata loaded", start.elapsed(), index);
 
         // loop { }
 
         // merge all of the indices the worker maintains.
-        let prev_time = input_graph.time().clone();
+        let prev_time = *input_graph.time();
         handles.merge_to(&prev_time);
 
         // synchronize with other workers before reporting indices merged.
-        let prev_time = input_graph.time().clone();
+        let prev_time = *input_graph.time();
         input_graph.advance_to(prev_time.inner + 1);
         input_delta.advance_to(prev_time.inner + 1);
+        if let Some(ref mut input) = input_sizes { input.advance_to(prev_time.inner + 1); }
         root.step_while(|| probe.less_than(input_graph.time()));
         println!("{:?}\t[worker {}]\tindices merged", start.elapsed(), index);
+        if inspect {
+            let (forward, reverse) = handles.stats();
+            println!("{:?}\t[worker {}]\tforward index: {:?}", start.elapsed(), index, forward);
+            println!("{:?}\t[worker {}]\treverse index: {:?}", start.elapsed(), index, reverse);
+        }
+        if let Some(mut input) = input_loads.take() {
+            let (forward, _) = handles.stats();
+            input.advance_to(input_graph.time().inner);
+            input.send((index, forward.keys(), forward.values()));
+            input.close();
+        }
+
+        // issue the point query from the first worker, binding the first relation's attributes.
+        if let Some(mut input) = input_points.take() {
+            if let Some((src, dst)) = point {
+                if index == 0 {
+                    let mut binding = vec![None; attributes];
+                    binding[first.0] = Some(src);
+                    binding[first.1] = Some(dst);
+                    input.advance_to(input_graph.time().inner);
+                    input.send((0, binding));
+                }
+            }
+            input.close();
+        }
+
+        // updates are admitted only while few enough batches and updates are in flight, by default no 
+        // more updates at each worker than in a whole batch, and the indices are merged up to each 
+        // batch once it is complete.
+        let mut input_delta = admission::Admission::new(input_delta, probe.clone(), admit.map(|x| x.0).unwrap_or(1), admit.and_then(|x| x.1).unwrap_or(query_batch));
+        let mut in_flight = VecDeque::new();
+
+        // the input and cancellation of any attached query.
+        let mut attached: Option<Attached> = None;
 
         // issue queries and updates, using the remaining lines in the file.
         for (query_counter, line) in lines.enumerate() {
 
             // each worker is responsible for a fraction of the queries
             if query_counter % peers == index {
-                let good_line = line.ok().expect("EXCEPTION: read error");
-                if !good_line.starts_with('#') && good_line.len() > 0 {
+                let good_line = line.expect("EXCEPTION: read error");
+                if !good_line.starts_with('#') && !good_line.is_empty() {
                     let mut elements = good_line[..].split_whitespace();
-                    let src: Node = elements.next().unwrap().parse().ok().expect("malformed src");
-                    let dst: Node = elements.next().unwrap().parse().ok().expect("malformed dst");
-                    input_delta.send(((src, dst), 1));
+                    let src: Node = elements.next().unwrap().parse().expect("malformed src");
+                    let dst: Node = elements.next().unwrap().parse().expect("malformed dst");
+                    input_delta.send(((src, dst), 1), || { root.step(); });
+                    if let Some((ref mut input, _)) = attached {
+                        input.send(((src, dst), 1));
+                    }
                 }
             }
 
             // synchronize and merge indices.
             if query_counter % query_batch == (query_batch - 1) {
-                let prev_time = input_graph.time().clone();
+                let prev_time = *input_graph.time();
+                if let Some(ref mut input) = input_sizes {
+                    input.send(handles.stats().0.values() as u64);
+                    input.advance_to(prev_time.inner + 1);
+                }
                 input_graph.advance_to(prev_time.inner + 1);
-                input_delta.advance_to(prev_time.inner + 1);
-                root.step_while(|| probe.less_than(input_delta.time()));
-                handles.merge_to(&prev_time);
+                input_delta.advance_to(prev_time.inner + 1, || { root.step(); });
+                if let Some((ref mut input, _)) = attached {
+                    input.advance_to(prev_time.inner + 1);
+                }
+                if admit.is_none() {
+                    root.step_while(|| probe.less_than(input_delta.time()));
+                }
+
+                // the handles themselves hold back merges to times an attached query has not passed.
+                in_flight.push_back(prev_time);
+                while in_flight.front().map(|time| !probe.less_equal(time)).unwrap_or(false) {
+                    handles.merge_to(&in_flight.pop_front().unwrap());
+                }
+
+                // the indices no longer reflect the graph once they refuse updates.
+                if let Some(error) = handles.exceeded() {
+                    println!("{:?}\t[worker {}]\tstopping: {}", start.elapsed(), index, error);
+                    break;
+                }
+                // nor are the results complete once a cross product stops.
+                if let Some(error) = handles.cross_exceeded() {
+                    println!("{:?}\t[worker {}]\tstopping: {}", start.elapsed(), index, error);
+                    break;
+                }
+
+                // attach a query reporting changes from the next batch on, or retire it.
+                let batches = query_counter / query_batch + 1;
+                if attach == Some(batches) {
+                    let from = input_delta.time().inner;
+                    let send = attached_send.clone();
+                    let (mut input, cancel) = root.dataflow::<Node,_,_>(|builder| {
+                        let (delta_input, delta) = builder.new_input::<((Node, Node), i32)>();
+                        let (graph_index, cancel) = shared.attach(&delta);
+                        let motifs = graph_index.track_motif(&attached_motif);
+                        if inspect {
+                            motifs
+                                .count()
+                                .inspect_batch(|t,x| println!("{:?}: {:?} (attached)", t, x))
+                                .inspect_batch(move |_,x| {
+                                    if let Ok(mut bound) = send.lock() {
+                                        *bound += x[0];
+                                    }
+                                });
+                        }
+                        (delta_input, cancel)
+                    });
+                    input.advance_to(from);
+                    attached = Some((input, cancel));
+                    println!("{:?}\t[worker {}]\tattached at {:?}", start.elapsed(), index, from);
+                }
+                if retire == Some(batches) {
+                    if let Some((input, cancel)) = attached.take() {
+                        cancel.cancel();
+                        input.close();
+                        println!("{:?}\t[worker {}]\tretired", start.elapsed(), index);
+                    }
+                }
+                if counted {
+                    let mut complete = counts.borrow_mut().drain(..).collect::<Vec<_>>();
+                    complete.sort_by_key(|x| x.1 );
+                    if fanout {
+                        for &(ref time, stage, ref count) in complete.iter() {
+                            println!("{:?}\t[worker {}]\tfanout: {:?}\t{:?}\t{:?}", start.elapsed(), index, time, stage, count);
+                        }
+                    }
+                    // the edges are those the indices of all workers held as each batch was closed.
+                    if let Some(ref audit) = audit {
+                        let mut times = complete.iter().map(|x| x.0).collect::<Vec<_>>();
+                        times.sort();
+                        times.dedup();
+                        for time in times {
+                            let edges = sizes.borrow_mut().remove(&time).unwrap_or(0) as f64;
+                            let counts = complete.iter().filter(|x| x.0 == time).cloned().collect::<Vec<_>>();
+                            for violation in audit.check(edges, query_batch as u64, &counts) {
+                                println!("{:?}\t[worker {}]\taudit: {:?}", start.elapsed(), index, violation);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // report any queries that exceeded their limits, once all work is complete.
+        if let Some(guard) = events {
+            input_graph.close();
+            input_delta.close();
+            if let Some(input) = input_sizes.take() { input.close(); }
+            if let Some((input, _)) = attached.take() { input.close(); }
+            while root.step() { }
+            for event in guard.events() {
+                println!("{:?}\t[worker {}]\tguard: {:?}", start.elapsed(), index, event);
             }
         }
     }).unwrap();
 
     let total = send2.lock().map(|x| *x).unwrap_or(0);
     println!("elapsed: {:?}\ttotal motifs at this process: {:?}", start.elapsed(), total); 
+}
+
+// reads the degree of each node in the edge file.
+fn degrees(filename: &str) -> Vec<usize> {
+    let file = File::open(Path::new(filename)).expect("EXCEPTION: couldn't open file");
+    let mut degrees = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let good_line = line.expect("EXCEPTION: read error");
+        if !good_line.starts_with('#') && !good_line.is_empty() {
+            let mut elements = good_line[..].split_whitespace();
+            let src: usize = elements.next().unwrap().parse().expect("malformed src");
+            let dst: usize = elements.next().unwrap().parse().expect("malformed dst");
+            let max = if src < dst { dst } else { src };
+            if degrees.len() <= max { degrees.resize(max + 1, 0); }
+            degrees[src] += 1;
+            degrees[dst] += 1;
+        }
+    }
+    degrees
 }
\ No newline at end of file
@@ -47,7 +47,7 @@ fn main () {
 
             // we will index the data both by src and dst.
             // let (forward, f_handle) = dG.index_from(&dG.filter(|_| false).map(|_| (0,0)));
-            let forward = IndexStream::from(|k| k as u64, 
+            let forward = IndexStream::from(partition::mix, 
                                             &dG.map(|((x,y),_)| (x,y)),
                                             &Vec::new().to_stream(builder));
 
@@ -0,0 +1,188 @@
+extern crate timely;
+extern crate alg3_dynamic;
+
+use std::collections::BTreeMap;
+use std::collections::Bound;
+
+use alg3_dynamic::*;
+
+use timely::dataflow::operators::*;
+
+/// A stand-in for an embedded ordered key-value store, as an alternative `IndexBackend`.
+///
+/// Each `(key, val)` pair maps to its committed count and its uncommitted timestamped updates,
+/// and the values for a key are found by a range scan, as they would be in a real store.
+struct OrderedStore<T> {
+    map: BTreeMap<(u32, u32), Entry<T>>,
+}
+
+// the committed count of a `(key, val)` pair, and its uncommitted timestamped updates.
+type Entry<T> = (i32, Vec<(T, i32)>);
+
+impl<T: Ord+Clone> OrderedStore<T> {
+
+    fn new() -> Self { OrderedStore { map: BTreeMap::new() } }
+
+    // the accumulated count for an entry, including updates at valid times.
+    fn accumulate<Valid: Fn(&T)->bool>(entry: &Entry<T>, valid: &Valid) -> i32 {
+        entry.0 + entry.1.iter().filter(|x| valid(&x.0)).map(|x| x.1).sum::<i32>()
+    }
+}
+
+impl<T: Ord+Clone> IndexBackend<u32, u32, T> for OrderedStore<T> {
+
+    fn count<P, F, Valid, W>(&mut self, data: &mut Vec<(P, u64, u64, W)>, func: &F, _valid: &Valid, ident: u64)
+    where F: Fn(&P)->u32, Valid: Fn(&T)->bool {
+        for record in data.iter_mut() {
+            let key = func(&record.0);
+            let count = self.map.range((key, 0) ..= (key, u32::MAX)).count() as u64;
+            if count < record.1 {
+                record.1 = count;
+                record.2 = ident;
+            }
+        }
+    }
+
+    fn propose<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Option<u32>, Vec<u32>, W)>, func: &F, valid: &Valid, limit: usize)
+    where F: Fn(&P)->u32, Valid: Fn(&T)->bool {
+        for record in data.iter_mut() {
+            let key = func(&record.0);
+            let start = match record.1.take() {
+                Some(after) => Bound::Excluded((key, after)),
+                None => Bound::Included((key, 0)),
+            };
+            for (&(_, val), entry) in self.map.range((start, Bound::Included((key, u32::MAX)))) {
+                // resume from the previous value, as we cannot tell if this is the last.
+                if record.2.len() >= limit {
+                    record.1 = record.2.last().cloned();
+                    break;
+                }
+                for _ in 0 .. OrderedStore::accumulate(entry, valid) {
+                    record.2.push(val);
+                }
+            }
+        }
+    }
+
+    fn intersect<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Vec<u32>, W)>, func: &F, valid: &Valid)
+    where F: Fn(&P)->u32, Valid: Fn(&T)->bool {
+        for record in data.iter_mut() {
+            let key = func(&record.0);
+            let map = &self.map;
+            record.1.retain(|&val| map.get(&(key, val)).map(|entry| OrderedStore::accumulate(entry, valid) > 0).unwrap_or(false));
+        }
+    }
+
+    fn update(&mut self, time: T, updates: &mut Vec<((u32, u32), i32)>) -> Result<(), MemoryLimitExceeded> {
+        for (pair, diff) in updates.drain(..) {
+            self.map.entry(pair).or_insert((0, Vec::new())).1.push((time.clone(), diff));
+        }
+        Ok(())
+    }
+
+    fn merge_to(&mut self, time: &T) {
+        for entry in self.map.values_mut() {
+            let committed = entry.1.iter().filter(|x| x.0.le(time)).map(|x| x.1).sum::<i32>();
+            entry.0 += committed;
+            entry.1.retain(|x| !x.0.le(time));
+        }
+        self.map = ::std::mem::take(&mut self.map)
+                       .into_iter()
+                       .filter(|x| (x.1).0 != 0 || !(x.1).1.is_empty())
+                       .collect();
+    }
+
+    fn initialize(&mut self, initial: &mut Vec<Vec<(u32, u32)>>) {
+        for pair in initial.drain(..).flat_map(|x| x.into_iter()) {
+            self.map.entry(pair).or_insert((0, Vec::new())).0 += 1;
+        }
+    }
+}
+
+// xorshift, to avoid depending on `rand`.
+fn next(seed: &mut u64) -> u64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    *seed
+}
+
+/// Maintains triangle counts over a random graph, with both indices held in `OrderedStore`s.
+///
+/// Usage: `triangles-backend <nodes> <edges> <batch> [inspect]`.
+#[allow(non_snake_case)]
+fn main () {
+
+    let nodes: u64 = std::env::args().nth(1).unwrap().parse().unwrap();
+    let edges: usize = std::env::args().nth(2).unwrap().parse().unwrap();
+    let batch: usize = std::env::args().nth(3).unwrap().parse().unwrap();
+    let inspect = ::std::env::args().find(|x| x == "inspect").is_some();
+
+    timely::execute_from_args(std::env::args(), move |root| {
+
+        let index = root.index();
+        let peers = root.peers();
+
+        let (mut input, probe, forward, reverse) = root.dataflow::<u32,_,_>(|builder| {
+
+            let (graph, dG) = builder.new_input::<((u32, u32), i32)>();
+
+            // as in `triangles`, but with user-supplied storage for both indices.
+            let forward = IndexStream::from_backend(OrderedStore::new(), partition::mix, &Vec::new().to_stream(builder), &dG);
+            let reverse = IndexStream::from_backend(OrderedStore::new(), partition::mix, &Vec::new().to_stream(builder), &dG.map(|((src,dst),wgt)| ((dst,src),wgt)));
+
+            let dK3dA = dG.extend(vec![Box::new(forward.extend_using(|&(x,_)| x, <_ as PartialOrd>::lt)),
+                                       Box::new(forward.extend_using(|&(_,y)| y, <_ as PartialOrd>::lt))])
+                          .flat_map(|(p,es,w)| es.into_iter().map(move |e| ((p.0,p.1,e), w)));
+
+            let dK3dB = dG.extend(vec![Box::new(forward.extend_using(|&(x,_)| x, <_ as PartialOrd>::le)),
+                                       Box::new(reverse.extend_using(|&(_,z)| z, <_ as PartialOrd>::lt))])
+                          .flat_map(|(p,es,w)| es.into_iter().map(move |e| ((p.0,e,p.1), w)));
+
+            let dK3dC = dG.extend(vec![Box::new(reverse.extend_using(|&(y,_)| y, <_ as PartialOrd>::le)),
+                                       Box::new(reverse.extend_using(|&(_,z)| z, <_ as PartialOrd>::le))])
+                          .flat_map(|(p,es,w)| es.into_iter().map(move |e| ((e,p.0,p.1), w)));
+
+            let cliques = dK3dC.concat(&dK3dB).concat(&dK3dA);
+
+            if inspect {
+                cliques.exchange(|x| (x.0).0 as u64)
+                       .count()
+                       .inspect_batch(move |t,x| println!("{:?}: {:?}", t, x));
+            }
+
+            (graph, cliques.probe(), forward, reverse)
+        });
+
+        let start = ::std::time::Instant::now();
+
+        let mut seed = 0x2545F4914F6CDD1Du64 + index as u64;
+        for edge in 0 .. edges {
+
+            // introduce the edge if it is this worker's responsibility
+            if edge % peers == index {
+                let src = (next(&mut seed) % nodes) as u32;
+                let dst = (next(&mut seed) % nodes) as u32;
+                input.send(((src, dst), 1));
+            }
+
+            // if at a batch boundary, advance time and do work.
+            if edge % batch == (batch - 1) {
+                let prev = *input.time();
+                input.advance_to(prev.inner + 1);
+                root.step_while(|| probe.less_than(input.time()));
+
+                forward.index.borrow_mut().merge_to(&prev);
+                reverse.index.borrow_mut().merge_to(&prev);
+            }
+        }
+
+        input.close();
+        while root.step() { }
+
+        if inspect {
+            println!("worker {} elapsed: {:?}", index, start.elapsed());
+        }
+
+    }).unwrap();
+}
@@ -43,8 +43,8 @@ fn main () {
             // The updates also use the other relations with slightly stale data: updates to each
             // relation must not see updates for "later" relations (under some order on relations).
 
-            let forward = IndexStream::from(|k| k as u64, &Vec::new().to_stream(builder), &dG);
-            let reverse = IndexStream::from(|k| k as u64, &Vec::new().to_stream(builder), &dG.map(|((src,dst),wgt)| ((dst,src),wgt)));
+            let forward = IndexStream::from(partition::mix, &Vec::new().to_stream(builder), &dG);
+            let reverse = IndexStream::from(partition::mix, &Vec::new().to_stream(builder), &dG.map(|((src,dst),wgt)| ((dst,src),wgt)));
 
             // dA(x,y) extends to z first through C(x,z) then B(y,z), both using forward indices.
             let dK3dA = dG.extend(vec![Box::new(forward.extend_using(|&(x,_)| x, <_ as PartialOrd>::lt)),
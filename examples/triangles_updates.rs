@@ -46,8 +46,8 @@ fn main () {
             // The updates also use the other relations with slightly stale data: updates to each
             // relation must not see updates for "later" relations (under some order on relations).
 
-            let forward = IndexStream::from(|k| k as u64, &dG, &dQ);
-            let reverse = IndexStream::from(|k| k as u64, &dG.map(|(src,dst)| (dst,src)), &dQ.map(|((src,dst),wgt)| ((dst,src),wgt)));
+            let forward = IndexStream::from(partition::mix, &dG, &dQ);
+            let reverse = IndexStream::from(partition::mix, &dG.map(|(src,dst)| (dst,src)), &dQ.map(|((src,dst),wgt)| ((dst,src),wgt)));
 
             // dA(x,y) extends to z first through C(x,z) then B(y,z), both using forward indices.
             let dK3dA = dQ//.filter(|_| false)
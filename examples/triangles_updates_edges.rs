@@ -51,8 +51,8 @@ fn main () {
             // relation must not see updates for "later" relations (under some order on relations).
 
             // we will index the data both by src and dst.
-            let forward = IndexStream::from(|k| k as u64, &dG, &dQ);
-            let reverse = IndexStream::from(|k| k as u64, &dG.map(|(src,dst)| (dst,src)),
+            let forward = IndexStream::from(partition::mix, &dG, &dQ);
+            let reverse = IndexStream::from(partition::mix, &dG.map(|(src,dst)| (dst,src)),
                                                            &dQ.map(|((src,dst),wgt)| ((dst,src),wgt)));
 
             // dA(x,y) extends to z first through C(x,z) then B(y,z), both using forward indices.
@@ -0,0 +1,103 @@
+//! Compares the intersection kernels with the scalar loops they replace.
+//!
+//! Run with `cargo run --release --example bench -- <large> <rounds>`, where `large` is the
+//! length of the longer list (default 1000000) and `rounds` the number of repetitions (default
+//! 10). For each ratio of list lengths, the program reports the time taken by the scalar loop
+//! previously used in `naive::intersect_and`, and by each of the kernels.
+
+extern crate intersection;
+
+use std::time::Instant;
+
+fn main() {
+
+    let large: usize = std::env::args().nth(1).map(|x| x.parse().unwrap()).unwrap_or(1_000_000);
+    let rounds: usize = std::env::args().nth(2).map(|x| x.parse().unwrap()).unwrap_or(10);
+
+    println!("simd available: {}", intersection::simd::available());
+    println!("ratio\tbaseline\tmerge\tgallop\tsimd\tintersect\t(ns per element of the longer list)");
+
+    let mut seed = 0x2545F4914F6CDD1Du64;
+    for &ratio in [1, 2, 4, 8, 16, 32, 64, 256, 1024].iter() {
+
+        // lists with a universe twice the longer length, so that about half of the smaller matches.
+        let mut bbb = (0 .. large).map(|_| (next(&mut seed) % (2 * large as u64)) as u32).collect::<Vec<_>>();
+        let mut aaa = (0 .. large / ratio).map(|_| (next(&mut seed) % (2 * large as u64)) as u32).collect::<Vec<_>>();
+        bbb.sort(); bbb.dedup();
+        aaa.sort(); aaa.dedup();
+
+        let baseline = time(rounds, large, || { let mut count = 0; baseline(&aaa, &bbb, |_| count += 1); count });
+        let merge = time(rounds, large, || { let mut count = 0; intersection::merge(&aaa, &bbb, |_,_| count += 1); count });
+        let gallop = time(rounds, large, || { let mut count = 0; intersection::gallop(&aaa, &bbb, |_,_| count += 1); count });
+        let simd = time(rounds, large, || { let mut count = 0; intersection::simd::intersect(&aaa, &bbb, |_,_| count += 1); count });
+        let intersect = time(rounds, large, || { let mut count = 0; intersection::intersect_lanes(&aaa, &bbb, |_,_| count += 1); count });
+
+        println!("{}\t{:.3}\t{:.3}\t{:.3}\t{:.3}\t{:.3}", ratio, baseline, merge, gallop, simd, intersect);
+    }
+}
+
+/// Reports the average nanoseconds per element of `large` over `rounds` calls to `logic`.
+fn time<F: FnMut()->usize>(rounds: usize, large: usize, mut logic: F) -> f64 {
+    let mut total = 0;
+    let start = Instant::now();
+    for _ in 0 .. rounds {
+        total += logic();
+    }
+    let elapsed = start.elapsed();
+    assert!(total > 0 || large == 0);
+    let nanos = elapsed.as_secs() as f64 * 1_000_000_000.0 + elapsed.subsec_nanos() as f64;
+    nanos / (rounds * large) as f64
+}
+
+// xorshift, to avoid depending on `rand`.
+fn next(seed: &mut u64) -> u64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    *seed
+}
+
+// the loop previously found in `naive::intersect_and`.
+fn baseline<F: FnMut(u32)>(aaa: &[u32], mut bbb: &[u32], mut func: F) {
+    if aaa.len() < bbb.len() / 16 {
+        for &a in aaa.iter() {
+            bbb = gallop_ge(bbb, &a);
+            if !bbb.is_empty() && bbb[0] == a {
+                func(a)
+            }
+        }
+    }
+    else {
+        for &a in aaa.iter() {
+            while !bbb.is_empty() && bbb[0] < a {
+                bbb = &bbb[1..];
+            }
+            if !bbb.is_empty() && a == bbb[0] {
+                func(a);
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn gallop_ge<'a, T: Ord>(mut slice: &'a [T], value: &T) -> &'a [T] {
+    if !slice.is_empty() && &slice[0] < value {
+        let mut step = 1;
+        while step < slice.len() && &slice[step] < value {
+            slice = &slice[step..];
+            step <<= 1;
+        }
+
+        step >>= 1;
+        while step > 0 {
+            if step < slice.len() && &slice[step] < value {
+                slice = &slice[step..];
+            }
+            step >>= 1;
+        }
+
+        slice = &slice[1..];
+    }
+
+    slice
+}
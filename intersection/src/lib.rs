@@ -0,0 +1,415 @@
+//! Intersection kernels for sorted lists.
+//!
+//! Each kernel walks a sorted list `a` and reports, for each element of `a` also found in a
+//! sorted list `b`, the pair `(i, j)` where `a[i]` equals `b[j]` and `j` is the first position
+//! in `b` holding that value. Repeated elements of `a` are each reported. Callers that want to
+//! treat repeated elements differently (e.g. matching them only once) can compare `a[i]` with
+//! `a[i-1]`, and callers that want multiplicities in `b` can walk forward from `j`.
+//!
+//! There are three strategies, which suit different shapes of input:
+//!
+//!   1. `merge` walks both lists linearly, and is best when they have similar lengths.
+//!   2. `gallop` leapfrogs through the lists using exponential search, and is best when one
+//!      list is much shorter than the other, as it does work logarithmic in the longer list.
+//!   3. `simd` compares blocks of `u32` values with vector instructions, and is best when the
+//!      lists have similar lengths and their elements are `u32`. It is only available on
+//!      `x86_64`, and uses AVX2 if the processor supports it and SSE2 otherwise.
+//!
+//! The `intersect` and `intersect_by` methods choose between the first two based on the ratio
+//! of the list lengths. The `intersect_lanes` method additionally uses `simd` for element types
+//! that implement `Lanes`, which reveals them as `u32` values.
+
+/// The length ratio above which `gallop` is preferred to `merge` or `simd`.
+pub const GALLOP_RATIO: usize = 64;
+
+/// Intersects sorted `a` and `b`, reporting `(i, j)` for each `a[i]` equal to a first `b[j]`.
+///
+/// The method chooses between galloping and merging, based on the relative lengths of `a` and `b`.
+#[inline]
+pub fn intersect<T: Ord, F: FnMut(usize, usize)>(a: &[T], b: &[T], report: F) {
+    if a.len() * GALLOP_RATIO < b.len() || b.len() * GALLOP_RATIO < a.len() {
+        gallop(a, b, report);
+    }
+    else {
+        merge(a, b, report);
+    }
+}
+
+/// Intersects sorted `a` and `b`, using vectorized merging where `T` is laid out as `u32` values.
+///
+/// This is `intersect` for element types that implement `Lanes`. Lists of similar lengths are
+/// merged with the `simd` kernels when `Lanes::as_u32s` reveals both as `u32` values.
+#[inline]
+pub fn intersect_lanes<T: Lanes, F: FnMut(usize, usize)>(a: &[T], b: &[T], report: F) {
+    match (T::as_u32s(a), T::as_u32s(b)) {
+        (Some(a32), Some(b32)) => intersect_u32s(a32, b32, report),
+        _ => intersect(a, b, report),
+    }
+}
+
+/// Intersects sorted `u32` lists, choosing between galloping and vectorized merging.
+#[inline]
+pub fn intersect_u32s<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], report: F) {
+    if a.len() * GALLOP_RATIO < b.len() || b.len() * GALLOP_RATIO < a.len() {
+        gallop(a, b, report);
+    }
+    else {
+        simd::intersect(a, b, report);
+    }
+}
+
+/// Element types whose lists can be compared by the `simd` kernels.
+///
+/// An implementation reveals a slice as `u32` values, if that is how the type is laid out, and
+/// otherwise returns `None` and leaves intersection to the scalar kernels.
+pub trait Lanes: Ord+Sized {
+    /// Reveals `slice` as `u32` values, if possible.
+    fn as_u32s(slice: &[Self]) -> Option<&[u32]>;
+}
+
+impl Lanes for u32 {
+    #[inline(always)]
+    fn as_u32s(slice: &[u32]) -> Option<&[u32]> { Some(slice) }
+}
+
+/// Intersects sorted `a` with sorted `b` as viewed through `key`.
+///
+/// This is the variant of `intersect` for when the elements of `b` are not themselves the values
+/// to match, for example `(value, count)` pairs. It chooses between galloping and merging, as the
+/// elements of `b` are not laid out for vectorized comparison.
+#[inline]
+pub fn intersect_by<A: Ord, B, K: Fn(&B)->&A, F: FnMut(usize, usize)>(a: &[A], b: &[B], key: K, report: F) {
+    if a.len() * GALLOP_RATIO < b.len() || b.len() * GALLOP_RATIO < a.len() {
+        gallop_by(a, b, key, report);
+    }
+    else {
+        merge_by(a, b, key, report);
+    }
+}
+
+/// Intersects by walking both lists linearly.
+#[inline]
+pub fn merge<T: Ord, F: FnMut(usize, usize)>(a: &[T], b: &[T], report: F) {
+    merge_by(a, b, |x| x, report)
+}
+
+/// Intersects by walking both lists linearly, with `b` viewed through `key`.
+#[inline]
+pub fn merge_by<A: Ord, B, K: Fn(&B)->&A, F: FnMut(usize, usize)>(a: &[A], b: &[B], key: K, mut report: F) {
+    let mut j = 0;
+    for (i, value) in a.iter().enumerate() {
+        while j < b.len() && key(&b[j]) < value { j += 1; }
+        if j == b.len() { return; }
+        if key(&b[j]) == value { report(i, j); }
+    }
+}
+
+/// Intersects by leapfrogging through both lists with exponential search.
+#[inline]
+pub fn gallop<T: Ord, F: FnMut(usize, usize)>(a: &[T], b: &[T], report: F) {
+    gallop_by(a, b, |x| x, report)
+}
+
+/// Intersects by leapfrogging through both lists with exponential search, with `b` viewed
+/// through `key`.
+#[inline]
+pub fn gallop_by<A: Ord, B, K: Fn(&B)->&A, F: FnMut(usize, usize)>(a: &[A], b: &[B], key: K, mut report: F) {
+
+    use std::cmp::Ordering;
+
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(key(&b[j])) {
+            Ordering::Less => {
+                i += 1 + advance(&a[(i+1)..], |x| x < key(&b[j]));
+            },
+            Ordering::Equal => {
+                // do not advance `j`; the next element of `a` may be equal.
+                report(i, j);
+                i += 1;
+            },
+            Ordering::Greater => {
+                j += 1 + advance(&b[(j+1)..], |x| key(x) < &a[i]);
+            },
+        }
+    }
+}
+
+/// Reports the number of elements satisfing the predicate.
+///
+/// This methods *relies strongly* on the assumption that the predicate
+/// stays false once it becomes false, a joint property of the predicate
+/// and the slice. This allows `advance` to use exponential search to
+/// count the number of elements in time logarithmic in the result.
+#[inline]
+pub fn advance<T, F: Fn(&T)->bool>(slice: &[T], function: F) -> usize {
+
+    // start with no advance
+    let mut index = 0;
+    if index < slice.len() && function(&slice[index]) {
+
+        // advance in exponentially growing steps.
+        let mut step = 1;
+        while index + step < slice.len() && function(&slice[index + step]) {
+            index += step;
+            step <<= 1;
+        }
+
+        // advance in exponentially shrinking steps.
+        step >>= 1;
+        while step > 0 {
+            if index + step < slice.len() && function(&slice[index + step]) {
+                index += step;
+            }
+            step >>= 1;
+        }
+
+        index += 1;
+    }
+
+    index
+}
+
+pub mod simd {
+
+    //! Vectorized intersection of `u32` lists.
+    //!
+    //! The kernels here compare a block of `a` against a block of `b` all-to-all, by comparing
+    //! the `a` block against each rotation of the `b` block. Then whichever block has the smaller
+    //! last element is advanced, preferring to advance `a` when they are equal, so that elements
+    //! of `b` repeated across a block boundary are still seen by later blocks of `a`. When fewer
+    //! than a block of elements remain in either list, we finish with a scalar merge.
+
+    /// Intersects sorted `u32` lists, using the widest vector instructions available.
+    #[inline]
+    pub fn intersect<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], report: F) {
+        intersect_impl(a, b, report)
+    }
+
+    /// Intersects sorted `u32` lists with the SSE2 kernel, even if wider instructions are available.
+    ///
+    /// Every `x86_64` processor supports SSE2, so this is the kernel `intersect` falls back to.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn intersect_sse2<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], report: F) {
+        unsafe { x86::intersect_sse2(a, b, report) }
+    }
+
+    /// Reports whether a vectorized kernel is available on this processor.
+    pub fn available() -> bool { available_impl() }
+
+    #[cfg(target_arch = "x86_64")]
+    fn available_impl() -> bool { true }
+    #[cfg(not(target_arch = "x86_64"))]
+    fn available_impl() -> bool { false }
+
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn intersect_impl<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], report: F) {
+        if is_x86_feature_detected!("avx2") {
+            unsafe { x86::intersect_avx2(a, b, report) }
+        }
+        else {
+            unsafe { x86::intersect_sse2(a, b, report) }
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    #[inline]
+    fn intersect_impl<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], report: F) {
+        super::merge(a, b, report)
+    }
+
+    /// Reports matches in `mask` for the block of `a` at `i` against the block of `b` at `j`.
+    ///
+    /// Lanes already reported for this block of `a` are recorded in `reported`, and are skipped.
+    /// The reported position in `b` is walked back to the first occurrence of the value.
+    #[inline(always)]
+    fn report_block<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], i: usize, j: usize, mask: u32, reported: &mut u32, report: &mut F) {
+        let mut fresh = mask & !*reported;
+        *reported |= mask;
+        while fresh != 0 {
+            let lane = fresh.trailing_zeros() as usize;
+            fresh &= fresh - 1;
+            let value = a[i + lane];
+            let mut position = j;
+            while b[position] != value { position += 1; }
+            while position > 0 && b[position-1] == value { position -= 1; }
+            report(i + lane, position);
+        }
+    }
+
+    /// Completes an intersection from `(i, j)` with a scalar merge.
+    ///
+    /// Lanes of the block of `a` at `i` recorded in `reported` have already been reported.
+    #[inline(always)]
+    fn finish<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], i: usize, j: usize, reported: u32, report: &mut F) {
+        super::merge(&a[i..], &b[j..], |x, y| {
+            if x >= 32 || reported & (1 << x) == 0 {
+                report(i + x, j + y);
+            }
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod x86 {
+
+        use std::arch::x86_64::*;
+
+        use super::{report_block, finish};
+
+        #[target_feature(enable = "sse2")]
+        pub unsafe fn intersect_sse2<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], mut report: F) {
+
+            let mut i = 0;
+            let mut j = 0;
+            let mut reported = 0u32;
+
+            while i + 4 <= a.len() && j + 4 <= b.len() {
+
+                let va = _mm_loadu_si128(a.as_ptr().add(i) as *const __m128i);
+                let vb = _mm_loadu_si128(b.as_ptr().add(j) as *const __m128i);
+
+                // compare against each rotation of `vb`.
+                let mut eq = _mm_cmpeq_epi32(va, vb);
+                eq = _mm_or_si128(eq, _mm_cmpeq_epi32(va, _mm_shuffle_epi32(vb, 0b00_11_10_01)));
+                eq = _mm_or_si128(eq, _mm_cmpeq_epi32(va, _mm_shuffle_epi32(vb, 0b01_00_11_10)));
+                eq = _mm_or_si128(eq, _mm_cmpeq_epi32(va, _mm_shuffle_epi32(vb, 0b10_01_00_11)));
+
+                let mask = _mm_movemask_ps(_mm_castsi128_ps(eq)) as u32;
+                if mask != 0 {
+                    report_block(a, b, i, j, mask, &mut reported, &mut report);
+                }
+
+                if a[i + 3] <= b[j + 3] { i += 4; reported = 0; }
+                else                    { j += 4; }
+            }
+
+            finish(a, b, i, j, reported, &mut report);
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn intersect_avx2<F: FnMut(usize, usize)>(a: &[u32], b: &[u32], mut report: F) {
+
+            let mut i = 0;
+            let mut j = 0;
+            let mut reported = 0u32;
+
+            while i + 8 <= a.len() && j + 8 <= b.len() {
+
+                let va = _mm256_loadu_si256(a.as_ptr().add(i) as *const __m256i);
+                let pb = b.as_ptr().add(j) as *const i32;
+
+                // compare against each element of the `b` block, broadcast to all lanes. unlike
+                // rotating a register, the comparisons are independent and can be overlapped.
+                let eq01 = _mm256_or_si256(_mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb)), _mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(1))));
+                let eq23 = _mm256_or_si256(_mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(2))), _mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(3))));
+                let eq45 = _mm256_or_si256(_mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(4))), _mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(5))));
+                let eq67 = _mm256_or_si256(_mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(6))), _mm256_cmpeq_epi32(va, _mm256_set1_epi32(*pb.add(7))));
+                let eq = _mm256_or_si256(_mm256_or_si256(eq01, eq23), _mm256_or_si256(eq45, eq67));
+
+                let mask = _mm256_movemask_ps(_mm256_castsi256_ps(eq)) as u32;
+                if mask != 0 {
+                    report_block(a, b, i, j, mask, &mut reported, &mut report);
+                }
+
+                if a[i + 7] <= b[j + 7] { i += 8; reported = 0; }
+                else                    { j += 8; }
+            }
+
+            finish(a, b, i, j, reported, &mut report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Collects the `(i, j)` pairs reported by `kernel`.
+    fn pairs<F: Fn(&[u32], &[u32], &mut dyn FnMut(usize, usize))>(a: &[u32], b: &[u32], kernel: F) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        kernel(a, b, &mut |i, j| result.push((i, j)));
+        result
+    }
+
+    /// Checks each kernel against `merge`, in both argument orders.
+    fn check(a: &[u32], b: &[u32]) {
+        for &(a, b) in [(a, b), (b, a)].iter() {
+            let expected = pairs(a, b, |a, b, r| merge(a, b, r));
+            assert_eq!(pairs(a, b, |a, b, r| gallop(a, b, r)), expected, "gallop: {:?} {:?}", a, b);
+            assert_eq!(pairs(a, b, |a, b, r| intersect(a, b, r)), expected, "intersect: {:?} {:?}", a, b);
+            assert_eq!(pairs(a, b, |a, b, r| intersect_lanes(a, b, r)), expected, "intersect_lanes: {:?} {:?}", a, b);
+            assert_eq!(pairs(a, b, |a, b, r| simd::intersect(a, b, r)), expected, "simd: {:?} {:?}", a, b);
+            #[cfg(target_arch = "x86_64")]
+            assert_eq!(pairs(a, b, |a, b, r| simd::intersect_sse2(a, b, r)), expected, "sse2: {:?} {:?}", a, b);
+            let keyed = b.iter().map(|&x| (x, ())).collect::<Vec<_>>();
+            assert_eq!(pairs(a, b, |a, _, r| intersect_by(a, &keyed, |x| &x.0, r)), expected, "intersect_by: {:?} {:?}", a, b);
+        }
+    }
+
+    // xorshift, to avoid depending on `rand`.
+    fn next(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    /// A sorted list of `length` values drawn from `0 .. range`, with repeats.
+    fn sorted(seed: &mut u64, length: usize, range: u64) -> Vec<u32> {
+        let mut list = (0 .. length).map(|_| (next(seed) % range) as u32).collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+
+    #[test]
+    fn empty() {
+        check(&[], &[]);
+        check(&[], &[1, 2, 3]);
+        check(&[], &(0 .. 100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn disjoint() {
+        let evens = (0 .. 200).map(|x| 2 * x).collect::<Vec<_>>();
+        let odds = (0 .. 200).map(|x| 2 * x + 1).collect::<Vec<_>>();
+        check(&evens, &odds);
+        check(&(0 .. 100).collect::<Vec<_>>(), &(100 .. 300).collect::<Vec<_>>());
+        check(&[5], &odds);
+    }
+
+    #[test]
+    fn duplicate_boundaries() {
+        // runs of equal values that straddle the four and eight lane block boundaries.
+        for offset in 0 .. 9 {
+            for run in 1 .. 11 {
+                let mut a = (0 .. offset).collect::<Vec<u32>>();
+                a.extend(vec![offset; run]);
+                a.extend(offset + 1 .. offset + 20);
+                let mut b = (0 .. offset).filter(|x| x % 3 == 0).collect::<Vec<u32>>();
+                b.extend(vec![offset; run + offset as usize % 3]);
+                b.extend((offset + 1 .. offset + 20).filter(|x| x % 2 == 0));
+                check(&a, &b);
+                check(&a, &a);
+            }
+        }
+        check(&[u32::MAX; 9], &[u32::MAX; 5]);
+        check(&[0; 17], &[0; 8]);
+    }
+
+    #[test]
+    fn randomized() {
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        for round in 0 .. 2000 {
+            let a_len = (next(&mut seed) % 70) as usize;
+            let b_len = if round % 10 == 0 { (next(&mut seed) % 5000) as usize } else { (next(&mut seed) % 70) as usize };
+            let range = 1 + next(&mut seed) % 200;
+            let a = sorted(&mut seed, a_len, range);
+            let b = sorted(&mut seed, b_len, range);
+            check(&a, &b);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 extern crate graph_map;
+extern crate intersection;
 
 pub struct GraphMap {
     map: graph_map::GraphMMap,
@@ -38,30 +39,17 @@ impl GraphMap {
     }
 }
 
-pub fn intersect_and<F: FnMut(u32)>(aaa: &[u32], mut bbb: &[u32], mut func: F) {
+/// Calls `func` on each element common to the sorted lists `aaa` and `bbb`.
+///
+/// The work is done by `intersection::intersect_lanes`, which gallops, merges, or uses vectorized
+/// comparisons depending on the relative sizes of the lists.
+pub fn intersect_and<F: FnMut(u32)>(aaa: &[u32], bbb: &[u32], mut func: F) {
 
     if aaa.len() > bbb.len() {
         intersect_and(bbb, aaa, func);
     }
     else {
-        if aaa.len() < bbb.len() / 16 {
-            for &a in aaa.iter() {
-                bbb = gallop_ge(bbb, &a);
-                if bbb.len() > 0 && bbb[0] == a {
-                    func(a)
-                }
-            }
-        }
-        else {
-            for &a in aaa.iter() {
-                while bbb.len() > 0 && bbb[0] < a {
-                    bbb = &bbb[1..];
-                }
-                if bbb.len() > 0 && a == bbb[0] {
-                    func(a);
-                }
-            }
-        }
+        intersection::intersect_lanes(aaa, bbb, |index, _| func(aaa[index]));
     }
 }
 
@@ -0,0 +1,199 @@
+//! Admission control for the inputs of a computation.
+//!
+//! A timely dataflow input accepts records as fast as they are supplied, and a producer that
+//! only occasionally waits on the computation's probe may run far ahead of it, with all of the
+//! records it has introduced queued in the dataflow. An `Admission` wraps an input, and admits
+//! records and advances of its time only while the numbers of incomplete times and records at
+//! those times are within limits, as determined by a probe on the computation's output.
+//!
+//! A caller may either try to introduce records, and receive `Busy` when it should not, or
+//! supply a function that does work (for example, stepping the worker) until it may.
+
+use std::fmt;
+use std::error::Error;
+use std::collections::VecDeque;
+
+use timely::Data;
+use timely::progress::Timestamp;
+use timely::progress::timestamp::RootTimestamp;
+use timely::progress::nested::product::Product;
+use timely::dataflow::InputHandle;
+use timely::dataflow::ProbeHandle;
+
+/// Indicates that an `Admission` has reached one of its limits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Busy;
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input busy: too many outstanding times or records")
+    }
+}
+
+impl Error for Busy { }
+
+/// An input whose records are admitted only while the computation keeps up with them.
+///
+/// Records are in flight once the input has advanced past their time, until `probe` has passed 
+/// their time, and a time is in flight while it has records in flight. Records are admitted 
+/// while fewer than `max_records` are in flight or at the current time, and the input may advance
+/// while fewer than `max_times` times are in flight. Records at the current time are not yet in 
+/// flight, as they cannot complete until the input advances, and so at most `max_records` records
+/// may be sent at any one time.
+pub struct Admission<T: Timestamp, D: Data> {
+    input: InputHandle<T, D>,
+    probe: ProbeHandle<Product<RootTimestamp, T>>,
+    max_times: usize,
+    max_records: usize,
+    /// Times with records in flight and their counts, in the order they were sent.
+    in_flight: VecDeque<(Product<RootTimestamp, T>, usize)>,
+    records: usize,
+    /// Records sent at the current time.
+    current: usize,
+}
+
+impl<T: Timestamp, D: Data> Admission<T, D> {
+
+    /// Wraps `input`, whose records are complete when `probe` has passed their time.
+    pub fn new(input: InputHandle<T, D>, probe: ProbeHandle<Product<RootTimestamp, T>>, max_times: usize, max_records: usize) -> Self {
+        assert!(max_times > 0 && max_records > 0);
+        Admission {
+            input,
+            probe,
+            max_times,
+            max_records,
+            in_flight: VecDeque::new(),
+            records: 0,
+            current: 0,
+        }
+    }
+
+    /// The current time of the input.
+    pub fn time(&self) -> &Product<RootTimestamp, T> { self.input.time() }
+
+    /// The numbers of times and records in flight, respectively.
+    pub fn in_flight(&mut self) -> (usize, usize) {
+        self.release();
+        (self.in_flight.len(), self.records)
+    }
+
+    /// Indicates whether a record would be refused.
+    pub fn busy(&mut self) -> bool {
+        self.release();
+        self.records + self.current >= self.max_records
+    }
+
+    /// Sends `record` at the current time, unless too many records are outstanding.
+    pub fn try_send(&mut self, record: D) -> Result<(), Busy> {
+        if self.busy() { return Err(Busy); }
+        self.current += 1;
+        self.input.send(record);
+        Ok(())
+    }
+
+    /// Indicates whether advancing the time would be refused.
+    pub fn busy_times(&mut self) -> bool {
+        self.release();
+        self.in_flight.len() >= self.max_times
+    }
+
+    /// Advances the time of the input to `time`, unless too many times are in flight.
+    pub fn try_advance_to(&mut self, time: T) -> Result<(), Busy> {
+        if self.busy_times() { return Err(Busy); }
+        self.advance(time);
+        Ok(())
+    }
+
+    /// Sends `record`, first calling `work` until the record is admitted.
+    ///
+    /// The `work` function should advance the computation, for example by stepping the worker.
+    /// This method panics if `max_records` records have already been sent at the current time, 
+    /// as no work can complete them before the input advances.
+    pub fn send<F: FnMut()>(&mut self, record: D, mut work: F) {
+        while self.busy() {
+            assert!(self.records > 0, "more than `max_records` records sent at one time");
+            work();
+        }
+        self.try_send(record).expect("admission refused after it was not busy");
+    }
+
+    /// Advances the time of the input to `time`, first calling `work` until it is admitted.
+    pub fn advance_to<F: FnMut()>(&mut self, time: T, mut work: F) {
+        while self.busy_times() { work(); }
+        self.advance(time);
+    }
+
+    /// Closes the input.
+    pub fn close(self) { self.input.close(); }
+
+    // advances the input, putting any records at the current time in flight.
+    fn advance(&mut self, time: T) {
+        if self.current > 0 && self.input.time().inner != time {
+            self.in_flight.push_back((self.input.time().clone(), self.current));
+            self.records += self.current;
+            self.current = 0;
+        }
+        self.input.advance_to(time);
+    }
+
+    // retires times in flight that the probe has passed.
+    fn release(&mut self) {
+        while self.in_flight.front().map(|x| !self.probe.less_equal(&x.0)).unwrap_or(false) {
+            let (_, count) = self.in_flight.pop_front().unwrap();
+            self.records -= count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use timely::dataflow::operators::*;
+
+    use super::*;
+
+    #[test]
+    fn limits() {
+        timely::execute_from_args(Vec::<String>::new().into_iter(), |root| {
+
+            let (input, probe) = root.dataflow::<u64,_,_>(|builder| {
+                let (input, stream) = builder.new_input::<u64>();
+                (input, stream.probe())
+            });
+            let mut admission = Admission::new(input, probe.clone(), 2, 3);
+
+            // records at the current time count toward the record limit.
+            for record in 0 .. 3 { assert_eq!(admission.try_send(record), Ok(())); }
+            assert_eq!(admission.try_send(3), Err(Busy));
+            assert_eq!(admission.in_flight(), (0, 0));
+
+            // advancing puts them in flight, until the probe passes their time.
+            assert_eq!(admission.try_advance_to(1), Ok(()));
+            assert_eq!(admission.in_flight(), (1, 3));
+            assert!(admission.busy());
+            root.step_while(|| admission.in_flight().1 > 0);
+            assert_eq!(admission.in_flight(), (0, 0));
+            assert!(!admission.busy());
+
+            // times without records are never in flight.
+            assert_eq!(admission.try_advance_to(2), Ok(()));
+            assert_eq!(admission.in_flight(), (0, 0));
+
+            // each time with records is in flight, until `max_times` refuse advancing.
+            assert_eq!(admission.try_send(4), Ok(()));
+            assert_eq!(admission.try_advance_to(3), Ok(()));
+            assert_eq!(admission.try_send(5), Ok(()));
+            assert_eq!(admission.try_advance_to(4), Ok(()));
+            assert_eq!(admission.in_flight(), (2, 2));
+            assert!(admission.busy_times());
+            assert_eq!(admission.try_advance_to(5), Err(Busy));
+
+            // the blocking methods do work until admitted.
+            admission.advance_to(5, || { root.step(); });
+            assert!(admission.in_flight().0 < 2);
+            admission.send(6, || { root.step(); });
+            admission.close();
+            root.step_while(|| !probe.done());
+        }).unwrap().join().into_iter().for_each(|result| result.unwrap());
+    }
+}
@@ -0,0 +1,196 @@
+//! Worst-case bounds on the sizes of motif joins, and audits of the work done against them.
+//!
+//! A motif is a join of binary relations, one for each edge of its description. For any fractional
+//! edge cover of the motif, an assignment of weights `x_e` to its edges such that each attribute's
+//! edges have weights summing to at least one, the number of results is at most the product over
+//! edges of `|R_e|^x_e`. The least such product is the AGM bound (Atserias, Grohe, and Marx), and
+//! a worst-case optimal join performs work proportional to it.
+//!
+//! The cover is found by solving this linear program with the simplex method. As each relation has
+//! two attributes, every vertex of the program has weights in `{0, 1/2, 1}`, and so the weights
+//! found are rounded to halves.
+//!
+//! In particular, for each prefix GenericJoin proposes extensions only from the relation with the
+//! fewest, and the total number proposed at each stage for an update batch is at most the AGM
+//! bound of the motif with the updated relation replaced by the batch. An `Audit` compares the
+//! proposals counted for each stage with this bound, and reports stages that exceed it. As each
+//! relation of a motif is the same graph, every relation is updated by each update batch.
+
+use fanout::{Stage, StageCounts};
+
+/// A fractional edge cover of `description` minimizing the AGM bound for relations of `sizes`.
+///
+/// The result has a weight for each edge of the description. Relations of size at most one are
+/// treated as having size one, so that among covers with equal bounds, one is found with the least
+/// total weight.
+pub fn edge_cover(description: &[(usize, usize)], sizes: &[f64]) -> Vec<f64> {
+    assert_eq!(description.len(), sizes.len());
+    let attributes = description.iter().map(|&(x,y)| ::std::cmp::max(x, y) + 1).max().unwrap_or(0);
+    let edges = description.len();
+
+    // each weight costs the log of its relation's size, plus a little, so that least weights win ties.
+    let costs = sizes.iter().map(|&x| if x > 1.0 { x.ln() } else { 0.0 } + 1e-9).collect::<Vec<_>>();
+
+    // the dual program maximizes the sum of a weight `y_a` for each attribute, with the weights of 
+    // each edge's attributes summing to at most its cost. It is feasible at zero, and so we start
+    // there, with a slack variable for each edge in the basis. Each row is an edge's constraint, 
+    // with a column for each attribute, then each slack, then the bound.
+    let columns = attributes + edges;
+    let mut rows = description.iter().enumerate().map(|(edge, &(x,y))| {
+        let mut row = vec![0.0; columns + 1];
+        row[x] = 1.0;
+        row[y] = 1.0;
+        row[attributes + edge] = 1.0;
+        row[columns] = costs[edge];
+        row
+    }).collect::<Vec<_>>();
+    let mut basis = (attributes .. columns).collect::<Vec<_>>();
+
+    // the objective row holds the reduced costs, negated, and the objective value.
+    let mut objective = vec![0.0; columns + 1];
+    for value in objective[.. attributes].iter_mut() { *value = -1.0; }
+
+    // pivot on the first improving column, and the row whose bound is tightest, preferring the least
+    // basic variable; choosing the least indices prevents cycling.
+    while let Some(column) = (0 .. columns).find(|&c| objective[c] < -1e-12) {
+        let row = (0 .. edges)
+            .filter(|&r| rows[r][column] > 1e-12)
+            .min_by(|&r1, &r2| {
+                let ratio1 = rows[r1][columns] / rows[r1][column];
+                let ratio2 = rows[r2][columns] / rows[r2][column];
+                ratio1.partial_cmp(&ratio2).unwrap().then(basis[r1].cmp(&basis[r2]))
+            })
+            .expect("motif has an uncovered attribute");
+
+        let pivot = rows[row][column];
+        for value in rows[row].iter_mut() { *value /= pivot; }
+        let pivot_row = rows[row].clone();
+        for (other, values) in rows.iter_mut().enumerate() {
+            let factor = values[column];
+            if other != row && factor != 0.0 {
+                for (value, &x) in values.iter_mut().zip(pivot_row.iter()) { *value -= factor * x; }
+            }
+        }
+        let factor = objective[column];
+        for (value, &x) in objective.iter_mut().zip(pivot_row.iter()) { *value -= factor * x; }
+        basis[row] = column;
+    }
+
+    // the weights of the cover are the reduced costs of the slack variables, at a vertex.
+    (0 .. edges).map(|edge| (objective[attributes + edge] * 2.0).round() / 2.0).collect()
+}
+
+/// The AGM bound on the results of `description` for relations of `sizes`.
+pub fn agm_bound(description: &[(usize, usize)], sizes: &[f64]) -> f64 {
+    if sizes.iter().any(|&x| x <= 0.0) { return 0.0; }
+    edge_cover(description, sizes).iter().zip(sizes.iter()).map(|(&w, &s)| s.powf(w)).product()
+}
+
+/// For each relation of `description`, the AGM bound with that relation replaced by `updates`
+/// updates and each other relation of size `edges`.
+pub fn delta_bounds(description: &[(usize, usize)], edges: f64, updates: f64) -> Vec<f64> {
+    (0 .. description.len()).map(|relation| {
+        let sizes = (0 .. description.len()).map(|x| if x == relation { updates } else { edges }).collect::<Vec<_>>();
+        agm_bound(description, &sizes)
+    }).collect()
+}
+
+/// A stage whose proposals exceeded the AGM bound for its update batch.
+#[derive(Clone, Debug)]
+pub struct AuditViolation<T> {
+    /// The timestamp of the update batch.
+    pub time: T,
+    /// The stage whose proposals exceeded the bound.
+    pub stage: Stage,
+    /// The number of updates at the timestamp, at all workers.
+    pub updates: u64,
+    /// The number of extensions proposed by the stage.
+    pub proposed: u64,
+    /// The bound, including any slack.
+    pub bound: f64,
+}
+
+/// Compares the work of a motif query with its AGM bound.
+pub struct Audit {
+    query: usize,
+    description: Vec<(usize, usize)>,
+    slack: f64,
+}
+
+impl Audit {
+
+    /// Audits the query numbered `query` by its `Fanout`, which tracks `description`.
+    ///
+    /// Stages are reported if their proposals exceed `slack` times the bound.
+    pub fn new(query: usize, description: &[(usize, usize)], slack: f64) -> Self {
+        Audit { query, description: description.to_vec(), slack }
+    }
+
+    /// Reports the stages in `counts` whose proposals exceed the bound for `updates` updates to
+    /// a graph of `edges` edges.
+    ///
+    /// The counts are usually those of a single timestamp at a single worker, whose work is part
+    /// of that of all workers, and so `edges` and `updates` should be those of all workers.
+    pub fn check<T: Clone>(&self, edges: f64, updates: u64, counts: &[(T, Stage, StageCounts)]) -> Vec<AuditViolation<T>> {
+        let bounds = delta_bounds(&self.description, edges, updates as f64);
+        let mut violations = Vec::new();
+        for &(ref time, stage, ref count) in counts.iter().filter(|x| x.1.query == self.query) {
+            let bound = self.slack * bounds[stage.relation];
+            let proposed = count.proposed.iter().sum::<u64>();
+            if proposed as f64 > bound {
+                violations.push(AuditViolation {
+                    time: time.clone(),
+                    stage,
+                    updates,
+                    proposed,
+                    bound,
+                });
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn close(x: f64, y: f64) -> bool { (x - y).abs() <= 1e-6 * y.abs().max(1.0) }
+
+    #[test]
+    fn triangle_is_covered_by_halves() {
+        let triangle = [(0, 1), (1, 2), (0, 2)];
+        assert_eq!(edge_cover(&triangle, &[100.0, 100.0, 100.0]), vec![0.5, 0.5, 0.5]);
+        assert!(close(agm_bound(&triangle, &[100.0, 100.0, 100.0]), 1000.0));
+
+        // the directed cycle is the same join.
+        let cycle = [(0, 1), (1, 2), (2, 0)];
+        assert_eq!(edge_cover(&cycle, &[100.0, 100.0, 100.0]), vec![0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn path_is_covered_integrally() {
+        let path = [(0, 1), (1, 2), (2, 3)];
+        assert_eq!(edge_cover(&path, &[100.0, 100.0, 100.0]), vec![1.0, 0.0, 1.0]);
+        assert!(close(agm_bound(&path, &[100.0, 100.0, 100.0]), 10000.0));
+    }
+
+    #[test]
+    fn star_needs_every_edge() {
+        let star = [(0, 1), (0, 2), (0, 3)];
+        assert_eq!(edge_cover(&star, &[10.0, 20.0, 30.0]), vec![1.0, 1.0, 1.0]);
+        assert!(close(agm_bound(&star, &[10.0, 20.0, 30.0]), 6000.0));
+    }
+
+    #[test]
+    fn bounds_of_small_relations() {
+        let triangle = [(0, 1), (1, 2), (0, 2)];
+        assert_eq!(agm_bound(&triangle, &[0.0, 100.0, 100.0]), 0.0);
+
+        // replacing one relation with a batch of four updates bounds proposals by 4^½ 100^½ 100^½.
+        let bounds = delta_bounds(&triangle, 100.0, 4.0);
+        assert_eq!(bounds.len(), 3);
+        assert!(bounds.iter().all(|&bound| close(bound, 200.0)), "{:?}", bounds);
+    }
+}
@@ -1,18 +1,19 @@
 use std::rc::Rc;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use std::fmt::Debug;
 use std::hash::Hash;
 
 use timely::ExchangeData;
 use timely::dataflow::{Stream, Scope};
-use timely::dataflow::operators::{Probe, Operator};
-use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::operators::{Probe, Operator, Broadcast, Capability, CapabilityRef};
+use timely::dataflow::channels::pact::{Exchange, Pipeline, ParallelizationContract};
 use timely::progress::Timestamp;
 use timely::dataflow::operators::probe::Handle as ProbeHandle;
 
-use {Index, StreamPrefixExtender};
+use {Index, IndexBackend, MemoryLimitExceeded, DenseKey, StreamPrefixExtender, Route};
+use spill::{Spill, Spilled, reload, spill_latest};
 
 /// An index materialized from streamed updates.
 ///
@@ -22,23 +23,219 @@ use {Index, StreamPrefixExtender};
 /// about whether outstanding times might still exist less than any query time.
 /// There is also a function `hash` from the key type `K` to `u64` values to indicate how
 /// the data are partitioned, so that users can align their query streams.
-pub struct IndexStream<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp> {
+///
+/// The index is stored in a `B: IndexBackend`, which is an in-memory `Index` unless another
+/// backend is supplied to `from_backend`.
+///
+/// Optionally, the values of hub keys, those with very many values, are replicated to every
+/// worker so that the work for these keys can be spread out; see `from_backend_with_hubs`.
+/// Small relations can instead be replicated in their entirety; see `from_replicated_backend`.
+///
+/// The extensions proposed for each prefix are produced in chunks of at most `chunk` values,
+/// which are intersected independently; see `set_chunk`. Extenders may also defer proposals
+/// until downstream operators have drained their input; see `set_drain`, and may write prefixes
+/// they cannot yet process to disk; see `set_spill`.
+pub struct IndexStream<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp, B=Index<K, V, T>> {
     /// Times completely absorded into the index.
     pub handle: ProbeHandle<T>,
     /// The index itself.
-    pub index: Rc<RefCell<Index<K, V, T>>>,
+    pub index: Rc<RefCell<B>>,
     /// A map from keys to u64 values, for distribution.
     pub hash: Rc<H>,
+    /// Identifies `hash` among the partitioners of the worker's indices.
+    pub partition: usize,
+    /// The first updates the index refused, after which it no longer reflects all updates.
+    pub exceeded: Rc<Cell<Option<MemoryLimitExceeded>>>,
+    hubs: Option<Hubs<K, B>>,
+    replicated: bool,
+    chunk: usize,
+    drain: Option<Drain<T>>,
+    spill: Option<Spill>,
+    cancelled: Option<Rc<Cell<bool>>>,
+    phantom: PhantomData<(K, V)>,
 }
 
+// the default bound on the number of extensions in each proposal.
+const DEFAULT_CHUNK: usize = 1 << 12;
 
-impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp+Ord> IndexStream<K, V, H, T> {
+// the number of extensions each `Propose` operator produces each time it is scheduled.
+const PROPOSE_EFFORT: usize = 1 << 16;
+
+/// Shared state for downstream-first execution of extension dataflows.
+///
+/// Timely dataflow schedules each operator in turn, and a `Propose` operator with much input may
+/// produce far more records than the operators downstream of it can promptly absorb. Operators
+/// sharing a `Drain` report the records they hold, and a `Propose` operator defers its work at
+/// each time while operators downstream of it hold records they could process without it.
+///
+/// Each operator is recorded by `register` as it is constructed, downstream of the operators at 
+/// the current `position`, and then becomes the position itself. Code constructing several 
+/// pipelines sets the position at the start of each, for example to an empty position for an 
+/// independent pipeline, or to an earlier position for a sibling branch, so that operators do 
+/// not defer to those of other pipelines. Operators that join pipelines are recorded downstream
+/// of each, by setting the position to the union of theirs.
+///
+/// Extenders report the times of records awaiting their index with `set_queued`; these records only
+/// defer work at the same or later times, and only once their index has passed their time, before
+/// which they cannot be processed. Sinks that hold records until their time is complete, for example
+/// cross products and guarded results, report the times they hold with `set_held`; these records only
+/// defer work at later times, whose work cannot be needed to complete them. Operators that pass 
+/// records straight through hold none, and need not register.
+pub struct Drain<T> {
+    // for each operator, the operators immediately upstream of it and the records it holds.
+    operators: Rc<RefCell<Vec<DrainOperator<T>>>>,
+    // the operators upstream of the next operator to be recorded.
+    position: Rc<RefCell<Vec<usize>>>,
+}
+
+struct DrainOperator<T> {
+    upstream: Vec<usize>,
+    queued: Vec<T>,
+    ready: Option<Rc<dyn Fn(&T)->bool>>,
+    held: Vec<T>,
+}
+
+impl<T> Clone for Drain<T> {
+    fn clone(&self) -> Self {
+        Drain { operators: self.operators.clone(), position: self.position.clone() }
+    }
+}
+
+impl<T: Timestamp> Default for Drain<T> {
+    fn default() -> Self { Drain::new() }
+}
+
+impl<T: Timestamp> Drain<T> {
+    /// Allocates a new `Drain`, with no operators.
+    pub fn new() -> Self {
+        Drain { operators: Rc::new(RefCell::new(Vec::new())), position: Rc::new(RefCell::new(Vec::new())) }
+    }
+    /// Records a new operator downstream of the current position, and returns its identifier.
+    ///
+    /// The new operator becomes the position, downstream of which the next is recorded.
+    pub fn register(&self) -> usize {
+        let mut operators = self.operators.borrow_mut();
+        let id = operators.len();
+        let upstream = ::std::mem::replace(&mut *self.position.borrow_mut(), vec![id]);
+        operators.push(DrainOperator { upstream, queued: Vec::new(), ready: None, held: Vec::new() });
+        id
+    }
+    /// The operators downstream of which the next operator will be recorded.
+    pub fn position(&self) -> Vec<usize> {
+        self.position.borrow().clone()
+    }
+    /// Records subsequently constructed operators downstream of the operators in `position`.
+    pub fn set_position(&self, mut position: Vec<usize>) {
+        position.sort();
+        position.dedup();
+        *self.position.borrow_mut() = position;
+    }
+    /// Records that the operator with identifier `id` holds records at each of `times`, which it can 
+    /// process once `probe` has passed their time.
+    pub fn set_queued(&self, id: usize, times: Vec<T>, probe: &ProbeHandle<T>) {
+        let mut operators = self.operators.borrow_mut();
+        operators[id].queued = times;
+        if operators[id].ready.is_none() {
+            let probe = probe.clone();
+            operators[id].ready = Some(Rc::new(move |time| !probe.less_equal(time)));
+        }
+    }
+    /// Records that the operator with identifier `id` holds records until each of `times` is complete.
+    pub fn set_held(&self, id: usize, times: Vec<T>) {
+        self.operators.borrow_mut()[id].held = times;
+    }
+    /// Indicates whether any operator downstream of operator `id` holds records it could process
+    /// without further work at `time` from operator `id`.
+    pub fn downstream_of(&self, id: usize, time: &T) -> bool {
+        let operators = self.operators.borrow();
+        // operators are recorded after those upstream of them, and so only later operators are
+        // downstream, and only later operators lie on paths to them.
+        (id + 1 .. operators.len()).any(|other| {
+            let operator = &operators[other];
+            let ready = |x: &T| operator.ready.as_ref().map(|ready| ready(x)).unwrap_or(true);
+            let holds = operator.queued.iter().any(|x| x.less_equal(time) && ready(x)) || operator.held.iter().any(|x| x.less_than(time));
+            holds && {
+                let mut visited = HashSet::new();
+                let mut todo = vec![other];
+                let mut found = false;
+                while let Some(next) = todo.pop() {
+                    for &upstream in operators[next].upstream.iter() {
+                        if upstream == id { found = true; }
+                        else if upstream > id && visited.insert(upstream) { todo.push(upstream); }
+                    }
+                }
+                found
+            }
+        })
+    }
+}
+
+/// Replicated storage for the values of hub keys.
+///
+/// Each worker holds the values of every hub key in `index`, and prefixes that need a hub
+/// key may be sent to any worker rather than to the key's home worker.
+struct Hubs<K, B> {
+    /// The hub keys, known to this worker once their values have been replicated to it.
+    keys: Rc<RefCell<HashSet<K>>>,
+    /// The replicated values of hub keys.
+    index: Rc<RefCell<B>>,
+}
+
+impl<K, B> Clone for Hubs<K, B> {
+    fn clone(&self) -> Self {
+        Hubs { keys: self.keys.clone(), index: self.index.clone() }
+    }
+}
+
+// clones share the index, so that extenders may be constructed in dataflows other than its own.
+impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp, B> Clone for IndexStream<K, V, H, T, B> {
+    fn clone(&self) -> Self {
+        IndexStream {
+            handle: self.handle.clone(),
+            index: self.index.clone(),
+            hash: self.hash.clone(),
+            partition: self.partition,
+            exceeded: self.exceeded.clone(),
+            hubs: self.hubs.clone(),
+            replicated: self.replicated,
+            chunk: self.chunk,
+            drain: self.drain.clone(),
+            spill: self.spill.clone(),
+            cancelled: self.cancelled.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+
+impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp+Ord, B: IndexBackend<K, V, T>> IndexStream<K, V, H, T, B> {
     /// Extends an `IndexStream` using the supplied functions.
     ///
     /// The `logic` function maps prefixes to index keys.
     /// The `func` function compares timestamps, acting as either `lt` or `le` depending
     /// on the need.
-    pub fn extend_using<P, L, F>(&self, logic: L, func: F) -> Rc<IndexExtender<K, V, T, P, L, H, F>>
+    pub fn extend_using<P, L, F>(&self, logic: L, func: F) -> Rc<IndexExtender<K, V, T, P, L, H, F, B>>
+    where
+        L: Fn(&P)->K+'static,
+        F: Fn(&T, &T)->bool+'static
+    {
+        self.extender(None, logic, func)
+    }
+
+    /// Extends an `IndexStream` keyed by the attribute `attribute` of each prefix.
+    ///
+    /// Consecutive extenders of the same `extend` call that key by the same attribute and share
+    /// a partitioner place prefixes identically, and the second of them does not exchange its
+    /// input. Each `IndexStream` has its own partitioner, unless constructed with `copartitioned`.
+    pub fn extend_using_attribute<P, F>(&self, attribute: usize, func: F) -> Rc<IndexExtender<K, V, T, P, impl Fn(&P)->K+'static, H, F, B>>
+    where
+        P: Attributes<K>,
+        F: Fn(&T, &T)->bool+'static
+    {
+        self.extender(Some(attribute), move |prefix: &P| prefix.attribute(attribute), func)
+    }
+
+    fn extender<P, L, F>(&self, key: Option<usize>, logic: L, func: F) -> Rc<IndexExtender<K, V, T, P, L, H, F, B>>
     where
         L: Fn(&P)->K+'static,
         F: Fn(&T, &T)->bool+'static
@@ -49,56 +246,190 @@ impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp+Ord> IndexStre
             hash: self.hash.clone(),
             logic: Rc::new(logic),
             valid: Rc::new(func),
+            hubs: self.hubs.clone(),
+            partition: self.partition,
+            key,
+            replicated: self.replicated,
+            chunk: self.chunk,
+            drain: self.drain.clone(),
+            spill: self.spill.clone(),
+            cancelled: self.cancelled.clone(),
             phantom: PhantomData,
         })
     }
 
-    /// Constructs an `IndexStream` from initial data and update stream.
+    /// The `Drain` coordinating subsequently constructed extenders, if any.
+    pub fn drain(&self) -> Option<Drain<T>> {
+        self.drain.clone()
+    }
+
+    /// Coordinates subsequently constructed extenders through `drain`, or ceases to if `None`.
     ///
-    /// Neither the initial stream nor the update stream are required to produce data.
-    /// The index can be static with no changes, or wholy dynamic with no starting data,
-    /// or a mix of both. If neither stream has any data, you are probably using the wrong
-    /// abstraction (though it will still work correctly).
-    pub fn from<G>(hash: H, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    /// Extenders sharing a `Drain` report the records they hold, and their proposals are 
+    /// deferred while operators downstream of them hold records. This bounds the intermediate
+    /// results in flight, at the expense of some parallelism between operators.
+    pub fn set_drain(&mut self, drain: Option<Drain<T>>) {
+        self.drain = drain;
+    }
+
+    /// Spills the prefixes blocked in subsequently constructed extenders as `spill` indicates,
+    /// or ceases to if `None`.
+    ///
+    /// Prefixes are blocked while the index has not accepted all updates at or before their 
+    /// time. Each operator holding more than `spill.threshold` blocked prefixes writes those at 
+    /// its latest times to files, and reads them back once they can be processed. Failures to 
+    /// write or read files are reported by `Spill::failed`.
+    pub fn set_spill(&mut self, spill: Option<Spill>) {
+        self.spill = spill;
+    }
+
+    /// Discards the records held by subsequently constructed extenders once `cancelled` is set,
+    /// or ceases to if `None`.
+    ///
+    /// Extenders discard their input and blocked prefixes, and drop their capabilities, rather
+    /// than wait for the index to reach the times of the prefixes.
+    pub fn set_cancelled(&mut self, cancelled: Option<Rc<Cell<bool>>>) {
+        self.cancelled = cancelled;
+    }
+
+    /// Bounds the number of extensions in each proposal by subsequently constructed extenders.
+    ///
+    /// The extensions for a prefix with more than `chunk` of them are proposed as several records,
+    /// each with the same prefix, which are then routed and intersected independently. Each is
+    /// read from the index where the previous left off, possibly when the operator is next
+    /// scheduled, and so the extensions are never all held at once. The default is 4096.
+    pub fn set_chunk(&mut self, chunk: usize) {
+        assert!(chunk > 0);
+        self.chunk = chunk;
+    }
+
+    /// Commits updates up to and including `time`, in the index and in any replicated hub values.
+    pub fn merge_to(&self, time: &T) {
+        self.index.borrow_mut().merge_to(time);
+        if let Some(ref hubs) = self.hubs {
+            hubs.index.borrow_mut().merge_to(time);
+        }
+    }
+
+    /// Constructs an `IndexStream` maintaining `backend` from initial data and update stream.
+    ///
+    /// Each worker supplies its own `backend`, which receives the initial data and updates for
+    /// the keys `hash` routes to the worker.
+    pub fn from_backend<G>(backend: B, hash: H, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
     where
         G: Scope<Timestamp=T>,
         K: ExchangeData,
         V: ExchangeData,
         T: Hash,
-        H: 'static
+        H: 'static,
+        B: 'static,
+    {
+        IndexStream::build(backend, None, Rc::new(hash), next_partition(), initially, updates)
+    }
+
+    /// Constructs an `IndexStream` maintaining `backend`, partitioned by the hash function of `self`.
+    ///
+    /// The two indices place keys identically, and extenders of each on the same attribute do
+    /// not need to exchange prefixes between them; see `extend_using_attribute`.
+    pub fn copartitioned<G>(&self, backend: B, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static,
+        B: 'static,
+    {
+        IndexStream::build(backend, None, self.hash.clone(), self.partition, initially, updates)
+    }
+
+    /// Constructs an `IndexStream` maintaining `backend`, with the values of hub keys replicated.
+    ///
+    /// A key with more than `threshold` values in the initial data is a hub. The values of hubs,
+    /// and later updates to them, are replicated to a `B::default()` on every worker, and the
+    /// extenders spread prefixes that need a hub across all workers, rather than sending them 
+    /// all to the hub's worker. Hubs are chosen from the initial data only, and are in effect 
+    /// from the time that data are loaded: a key that passes `threshold` through later updates 
+    /// is never replicated, and a hub stays replicated however few values updates leave it.
+    ///
+    /// Both stores must be merged, for which `IndexStream::merge_to` should be used.
+    pub fn from_backend_with_hubs<G>(backend: B, threshold: usize, hash: H, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static,
+        B: Default+'static,
+    {
+        IndexStream::build(backend, Some((threshold, B::default())), Rc::new(hash), next_partition(), initially, updates)
+    }
+
+    // maintains `backend` for the keys `hash` routes to this worker and, if `hubs` is supplied, 
+    // the replicated values of keys with more than the indicated number of values.
+    fn build<G>(backend: B, hubs: Option<(usize, B)>, hash: Rc<H>, partition: usize, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static,
+        B: 'static,
+    {
+        let hash_1 = hash.clone();      // used by exchange pact 1.
+        let hash_2 = hash.clone();      // used by exchange pact 2.
+
+        let exch1 = Exchange::new(move |x: &((K,V),i32)| (*hash_1)((x.0).0.clone()));
+        let exch2 = Exchange::new(move |x: &(K,V)| (*hash_2)(x.0.clone()));
+
+        IndexStream::build_using(backend, hubs, hash, partition, false, exch1, exch2, initially, updates)
+    }
+
+    // maintains `backend` from updates and initial data received using `exch1` and `exch2`.
+    #[allow(clippy::too_many_arguments)]
+    fn build_using<G, C1, C2>(backend: B, hubs: Option<(usize, B)>, hash: Rc<H>, partition: usize, replicated: bool, exch1: C1, exch2: C2, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static,
+        B: 'static,
+        C1: ParallelizationContract<T, ((K, V), i32)>,
+        C2: ParallelizationContract<T, (K, V)>,
     {
         use self::merge_sorter::MergeSorter;
 
         let worker_index = initially.scope().index();
 
-        let index_1 = Rc::new(RefCell::new(Index::new()));  // held by operator
+        let index_1 = Rc::new(RefCell::new(backend));       // held by operator
         let index_2 = index_1.clone();                      // returned in `IndexStream`.
 
-        let hash_1 = Rc::new(hash);     // used by exchange pact 1.
-        let hash_2 = hash_1.clone();    // used by exchange pact 2.
-        let hash_3 = hash_1.clone();    // returned in `IndexStream`.
+        // records the first updates refused by the index or the hub index.
+        let exceeded_1 = Rc::new(Cell::new(None));
+        let exceeded_2 = exceeded_1.clone();
+        let exceeded_3 = exceeded_1.clone();
 
         let mut map = HashMap::new();
         let mut sorter = Some(MergeSorter::new(|x: &(K,V)| x.clone()));
 
-        let exch1 = Exchange::new(move |x: &((K,V),i32)| (*hash_1)((x.0).0.clone()));
-        let exch2 = Exchange::new(move |x: &(K,V)| (*hash_2)(x.0.clone()));
-
         let mut buffer1 = Vec::new();
         let mut buffer2 = Vec::new();
 
-        let handle = updates.binary_notify(initially, exch1, exch2, "Index", vec![],
-            //::<_,(),_,_,_>
-            move |input1, input2,_output,notificator| {
+        // hub keys this worker is responsible for, whose values and updates it must replicate.
+        let threshold = hubs.as_ref().map(|x| x.0);
+        let mut local_hubs = HashSet::new();
 
+        // the output contains the initial values of hub keys (flagged `true`) and updates to them.
+        let replicate = updates.binary_notify(initially, exch1, exch2, "Index", vec![],
+            move |input1, input2,output,notificator| {
 
                 // extract, enqueue updates.
                 input1.for_each(|time, data| {
-                    if false { _output.session(&time).give(()); }
                     data.swap(&mut buffer1);
                     map.entry(time.time().clone())
                        .or_insert(Vec::new())
-                       .extend(buffer1.drain(..));
+                       .append(&mut buffer1);
                     notificator.notify_at(time.retain());
                 });
 
@@ -117,24 +448,245 @@ impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp+Ord> IndexStre
                         let mut sorted = Vec::new();
                         sorter.finish_into(&mut sorted);
                         let sum: usize = sorted.iter().map(|x| x.len()).sum();
-                        println!("worker {}: index built with {} elements", worker_index, sum);
+                        let mut keys = 0;
+                        let mut prev = None;
+                        for pair in sorted.iter().flat_map(|x| x.iter()) {
+                            if prev != Some(&pair.0) {
+                                keys += 1;
+                                prev = Some(&pair.0);
+                            }
+                        }
+                        println!("worker {}: index built with {} elements over {} keys", worker_index, sum, keys);
+
+                        // identify and replicate keys with more than `threshold` values.
+                        if let Some(threshold) = threshold {
+                            let mut session = output.session(&time);
+                            let mut pairs = sorted.iter().flat_map(|x| x.iter()).peekable();
+                            let mut run = Vec::new();
+                            while let Some(pair) = pairs.next() {
+                                run.push(pair);
+                                // at the end of a run of the same key, replicate if long enough.
+                                if pairs.peek().map(|x| x.0 != pair.0).unwrap_or(true) {
+                                    if run.len() > threshold {
+                                        local_hubs.insert(pair.0.clone());
+                                        for &hub_pair in run.iter() {
+                                            session.give((true, (hub_pair.clone(), 1)));
+                                        }
+                                    }
+                                    run.clear();
+                                }
+                            }
+                        }
+
                         index_1.borrow_mut().initialize(&mut sorted);
                     }
                     // push updates if updates exist
                     if let Some(mut list) = map.remove(time.time()) {
-                        index_1.borrow_mut().update(time.time().clone(), &mut list);
+                        if !local_hubs.is_empty() {
+                            let mut session = output.session(&time);
+                            for update in list.iter().filter(|x| local_hubs.contains(&(x.0).0)) {
+                                session.give((false, update.clone()));
+                            }
+                        }
+                        if let Err(error) = index_1.borrow_mut().update(time.time().clone(), &mut list) {
+                            if exceeded_1.get().is_none() { exceeded_1.set(Some(error)); }
+                        }
                     }
                 });
             }
-        ).probe();
+        );
+
+        let (handle, hubs) = match hubs {
+            None => (replicate.probe(), None),
+            Some((_, replicas)) => {
+
+                let hub_index_1 = Rc::new(RefCell::new(replicas));  // held by operator
+                let hub_index_2 = hub_index_1.clone();              // returned in `IndexStream`.
+                let hub_keys_1 = Rc::new(RefCell::new(HashSet::new()));
+                let hub_keys_2 = hub_keys_1.clone();
+
+                let mut stash = HashMap::new();
+                let mut initial = Vec::new();
+                let mut buffer = Vec::new();
+
+                // every worker receives the values of every hub, and maintains them in its own index.
+                let handle = replicate.broadcast().unary_notify(Pipeline, "IndexHubs", vec![], move |input, output, notificator| {
+
+                    input.for_each(|time, data| {
+                        if false { output.session(&time).give(()); }
+                        data.swap(&mut buffer);
+                        let entry = stash.entry(time.time().clone()).or_insert((Vec::new(), Vec::new()));
+                        for (is_initial, update) in buffer.drain(..) {
+                            if is_initial { entry.0.push(update.0); }
+                            else { entry.1.push(update); }
+                        }
+                        notificator.notify_at(time.retain());
+                    });
+
+                    notificator.for_each(|time,_,_| {
+                        if let Some((pairs, mut list)) = stash.remove(time.time()) {
+                            // workers may load initial data at different times, so we retain 
+                            // initial values in order to re-initialize with all of them.
+                            if !pairs.is_empty() {
+                                hub_keys_1.borrow_mut().extend(pairs.iter().map(|x| x.0.clone()));
+                                initial.extend(pairs);
+                                initial.sort();
+                                hub_index_1.borrow_mut().initialize(&mut vec![initial.clone()]);
+                            }
+                            if !list.is_empty() {
+                                if let Err(error) = hub_index_1.borrow_mut().update(time.time().clone(), &mut list) {
+                                    if exceeded_2.get().is_none() { exceeded_2.set(Some(error)); }
+                                }
+                            }
+                        }
+                    });
+                }).probe();
+
+                (handle, Some(Hubs { keys: hub_keys_2, index: hub_index_2 }))
+            },
+        };
 
         IndexStream {
-            handle: handle,
+            handle,
             index: index_2,
-            hash: hash_3,
+            hash,
+            partition,
+            exceeded: exceeded_3,
+            hubs,
+            replicated,
+            chunk: DEFAULT_CHUNK,
+            drain: None,
+            spill: None,
+            cancelled: None,
+            phantom: PhantomData,
         }
     }
+}
 
+thread_local! {
+    // the number of partitioners allocated by this worker.
+    static PARTITIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+// identifies a new partitioner. workers construct their dataflows identically, and so allocate
+// the same identifiers to the same partitioners.
+fn next_partition() -> usize {
+    PARTITIONS.with(|count| { count.set(count.get() + 1); count.get() })
+}
+
+/// Prefixes whose attributes can be read by position.
+///
+/// Extenders keyed by an attribute, from `IndexStream::extend_using_attribute`, read their keys
+/// through this trait, so that the attribute they report as their key is the one they read.
+pub trait Attributes<K> {
+    /// The value of the attribute at position `attribute`.
+    fn attribute(&self, attribute: usize) -> K;
+}
+
+impl<K: Clone> Attributes<K> for Vec<K> {
+    #[inline(always)] fn attribute(&self, attribute: usize) -> K { self[attribute].clone() }
+}
+impl<K: Clone> Attributes<K> for [K; 2] { #[inline(always)] fn attribute(&self, attribute: usize) -> K { self[attribute].clone() } }
+impl<K: Clone> Attributes<K> for [K; 3] { #[inline(always)] fn attribute(&self, attribute: usize) -> K { self[attribute].clone() } }
+impl<K: Clone> Attributes<K> for [K; 4] { #[inline(always)] fn attribute(&self, attribute: usize) -> K { self[attribute].clone() } }
+impl<K: Clone> Attributes<K> for [K; 5] { #[inline(always)] fn attribute(&self, attribute: usize) -> K { self[attribute].clone() } }
+impl<K: Clone> Attributes<K> for (K, K) {
+    #[inline(always)] fn attribute(&self, attribute: usize) -> K { [&self.0, &self.1][attribute].clone() }
+}
+impl<K: Clone> Attributes<K> for (K, K, K) {
+    #[inline(always)] fn attribute(&self, attribute: usize) -> K { [&self.0, &self.1, &self.2][attribute].clone() }
+}
+impl<K: Clone, R> Attributes<K> for (Vec<K>, R) { #[inline(always)] fn attribute(&self, attribute: usize) -> K { self.0[attribute].clone() } }
+
+// the hash function of a replicated `IndexStream`, which does not place keys.
+fn unplaced<K>(_key: K) -> u64 { 0 }
+
+impl<K: Ord+Hash+Clone, V: Ord+Clone, T: Timestamp+Ord, B: IndexBackend<K, V, T>> IndexStream<K, V, fn(K)->u64, T, B> {
+
+    /// Constructs an `IndexStream` maintaining `backend`, replicated in full at every worker.
+    ///
+    /// Every worker receives all initial data and updates, and so the extenders apply to prefixes
+    /// at whichever worker they are found, without exchanging them. This suits small relations, 
+    /// whose size is multiplied by the number of workers. The `hash` function is not used.
+    pub fn from_replicated_backend<G>(backend: B, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        B: 'static,
+    {
+        let hash = Rc::new(unplaced as fn(K)->u64);
+        IndexStream::build_using(backend, None, hash, next_partition(), true, Pipeline, Pipeline, &initially.broadcast(), &updates.broadcast())
+    }
+}
+
+impl<K: Ord+Hash+Clone, V: Ord+Clone, T: Timestamp+Ord> IndexStream<K, V, fn(K)->u64, T> {
+
+    /// Constructs an `IndexStream` from initial data and update stream, replicated in full at every worker.
+    ///
+    /// See `from_replicated_backend` for details.
+    pub fn from_replicated<G>(initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+    {
+        IndexStream::from_replicated_backend(Index::new(), initially, updates)
+    }
+}
+
+impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp+Ord> IndexStream<K, V, H, T> {
+
+    /// Constructs an `IndexStream` from initial data and update stream.
+    ///
+    /// Neither the initial stream nor the update stream are required to produce data.
+    /// The index can be static with no changes, or wholy dynamic with no starting data,
+    /// or a mix of both. If neither stream has any data, you are probably using the wrong
+    /// abstraction (though it will still work correctly).
+    pub fn from<G>(hash: H, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static
+    {
+        IndexStream::from_backend(Index::new(), hash, initially, updates)
+    }
+
+    /// Constructs an `IndexStream` from initial data and update stream, replicating hub keys.
+    ///
+    /// Keys with more than `threshold` initial values are replicated to every worker, as 
+    /// described for `from_backend_with_hubs`.
+    pub fn from_with_hubs<G>(threshold: usize, hash: H, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static
+    {
+        IndexStream::from_backend_with_hubs(Index::new(), threshold, hash, initially, updates)
+    }
+
+    /// Constructs an `IndexStream` with dense key storage from initial data and update stream.
+    ///
+    /// Each worker locates its keys by offset rather than by searching or hashing, which suits
+    /// compact integer keys. The `hash` function must route each key to worker `key % peers`,
+    /// as `|k| k as u64` does; the index panics on keys routed elsewhere.
+    pub fn from_dense<G>(hash: H, initially: &Stream<G, (K, V)>, updates: &Stream<G, ((K, V), i32)>) -> Self
+    where
+        G: Scope<Timestamp=T>,
+        K: ExchangeData+DenseKey,
+        V: ExchangeData,
+        T: Hash,
+        H: 'static
+    {
+        let scope = initially.scope();
+        IndexStream::from_backend(Index::dense(scope.index(), scope.peers()), hash, initially, updates)
+    }
 }
 
 
@@ -144,7 +696,7 @@ impl<K: Ord+Hash+Clone, V: Ord+Clone, H: Fn(K)->u64, T: Timestamp+Ord> IndexStre
 /// index simply by specifying how to extract a `&K` from a `&P`. In addition, we wrap up
 /// a "time validator" that indicates for times t1 and t2 whether updates at t1 should be
 /// included in answers for time t2.
-pub struct IndexExtender<K, V, T, P, L, H, F>
+pub struct IndexExtender<K, V, T, P, L, H, F, B=Index<K, V, T>>
 where
     K: Ord+Hash+Clone,
     V: Ord+Clone,
@@ -154,44 +706,125 @@ where
     F: Fn(&T, &T)->bool,
 {
     handle: ProbeHandle<T>,
-    index: Rc<RefCell<Index<K, V, T>>>,
+    index: Rc<RefCell<B>>,
     hash: Rc<H>,
     logic: Rc<L>,
     valid: Rc<F>,
-    phantom: PhantomData<P>,
+    hubs: Option<Hubs<K, B>>,
+    partition: usize,
+    key: Option<usize>,
+    replicated: bool,
+    chunk: usize,
+    drain: Option<Drain<T>>,
+    spill: Option<Spill>,
+    cancelled: Option<Rc<Cell<bool>>>,
+    phantom: PhantomData<(K, V, P)>,
 }
 
-impl<K, V, G, P, L, H, F, W> StreamPrefixExtender<G, W> for Rc<IndexExtender<K, V, G::Timestamp, P, L, H, F>>
+impl<K, V, T, P, L, H, F, B> IndexExtender<K, V, T, P, L, H, F, B>
+where
+    K: Ord+Hash+Clone,
+    V: Ord+Clone,
+    T: Timestamp,
+    L: Fn(&P)->K,
+    H: Fn(K)->u64,
+    F: Fn(&T, &T)->bool,
+{
+    /// A function routing prefixes to the workers holding the values of their keys.
+    ///
+    /// Prefixes are routed by the hash of their key, except that prefixes for hub keys are
+    /// spread round-robin across all workers, each of which holds the values of every hub.
+    fn router(&self) -> impl Fn(&P)->u64 {
+        let logic = self.logic.clone();
+        let hash = self.hash.clone();
+        let hubs = self.hubs.as_ref().map(|x| x.keys.clone());
+        let counter = Cell::new(0);
+        move |prefix| {
+            let key = (*logic)(prefix);
+            match hubs {
+                Some(ref keys) if keys.borrow().contains(&key) => {
+                    counter.set(counter.get() + 1);
+                    counter.get()
+                },
+                _ => (*hash)(key),
+            }
+        }
+    }
+}
+
+/// Applies `action` to `data` using `index`, except for records whose keys are hubs.
+///
+/// Records whose keys `key` reports as hubs are instead acted on using the replicated hub values.
+/// The records are left in `data`, though perhaps not in their original order.
+fn with_hubs<K, B, D, S, A>(data: &mut Vec<D>, index: &RefCell<B>, hubs: &Option<Hubs<K, B>>, key: S, mut action: A)
+where
+    K: Hash+Eq,
+    S: Fn(&D)->K,
+    A: FnMut(&mut B, &mut Vec<D>),
+{
+    match *hubs {
+        Some(ref hubs) if !hubs.keys.borrow().is_empty() => {
+            let keys = hubs.keys.borrow();
+            let (mut hub_data, mut rest): (Vec<_>, Vec<_>) = data.drain(..).partition(|x| keys.contains(&key(x)));
+            action(&mut index.borrow_mut(), &mut rest);
+            action(&mut hubs.index.borrow_mut(), &mut hub_data);
+            data.append(&mut rest);
+            data.append(&mut hub_data);
+        },
+        _ => action(&mut index.borrow_mut(), data),
+    }
+}
+
+/// The data blocked at `time`, in a list of blocked data ordered by time.
+///
+/// The `Count`, `Propose`, and `Intersect` operators retain data until the index has accepted all
+/// updates at or before its time, and visit the blocked data oldest first. Timely dataflow 
+/// schedules each operator at every step of the worker, and so an operator sees both its 
+/// remaining work and the advance of the index probe without needing to be activated.
+fn blocked_at<'a, T: Timestamp+Ord, D: Default>(blocked: &'a mut Vec<(Capability<T>, D)>, time: CapabilityRef<T>) -> &'a mut D {
+    let position = match blocked.binary_search_by(|x| x.0.time().cmp(time.time())) {
+        Ok(position) => position,
+        Err(position) => {
+            blocked.insert(position, (time.retain(), D::default()));
+            position
+        },
+    };
+    &mut blocked[position].1
+}
+
+// The operators for each method of `StreamPrefixExtender`, which receive prefixes using `pact`.
+impl<K, V, T, P, L, H, F, B> IndexExtender<K, V, T, P, L, H, F, B>
 where
     K: Ord+Hash+Clone+ExchangeData,
     V: Ord+Clone+ExchangeData,
-    G: Scope,
-    G::Timestamp: Timestamp+Ord+Clone,//+::std::hash::Hash+Ord,
+    T: Timestamp+Ord+Clone,
     P: ExchangeData+Debug,
     L: Fn(&P)->K+'static,
     H: Fn(K)->u64+'static,
-    F: Fn(&G::Timestamp, &G::Timestamp)->bool+'static,
-    W: ExchangeData,
+    F: Fn(&T, &T)->bool+'static,
+    B: IndexBackend<K, V, T>+'static,
 {
-    type Prefix = P;
-    type Extension = V;
-
-    fn count(&self, prefixes: Stream<G, (Self::Prefix, u64, u64, W)>, ident: u64) -> Stream<G, (Self::Prefix, u64, u64, W)> {
-
-        let hash = self.hash.clone();
+    fn count_with<G, W, C>(&self, prefixes: Stream<G, (P, u64, u64, W)>, ident: u64, pact: C) -> Stream<G, (P, u64, u64, W)>
+    where
+        G: Scope<Timestamp=T>,
+        W: ExchangeData,
+        C: ParallelizationContract<T, (P, u64, u64, W)>,
+    {
         let index = self.index.clone();
+        let hubs = self.hubs.clone();
         let logic1 = self.logic.clone();
         let logic2 = self.logic.clone();
         let valid = self.valid.clone();
 
         let handle = self.handle.clone();
-        let mut blocked = HashMap::new();//vec![];
+        let drain = self.drain.as_ref().map(|drain| (drain.clone(), drain.register()));
+        let spill = self.spill.clone();
+        let cancelled = self.cancelled.clone();
+        let mut blocked: Vec<(Capability<T>, Spilled<_>)> = Vec::new();
 
         let mut buffer1 = Vec::new();
 
-        let exch = Exchange::new(move |&(ref x,_,_,_)| (*hash)((*logic1)(x)));
-
-        prefixes.unary(exch, "Count", move |_,_| move |input, output| {
+        prefixes.unary(pact, "Count", move |_,_| move |input, output| {
 
             // The logic in this operator should only be applied to data inputs at `time` once we are
             // certain that the second input has also advanced to `time`. The shared index `clone` is
@@ -200,119 +833,277 @@ where
             //
             // The same structure also applies to `propose` and `intersect`, so these comments apply too.
 
-            // put all (time, data) pairs into a temporary list
+            // once cancelled, input and blocked prefixes are discarded, and capabilities dropped.
+            if cancelled.as_ref().map(|x| x.get()).unwrap_or(false) {
+                input.for_each(|_, data| { data.swap(&mut buffer1); buffer1.clear(); });
+                blocked.clear();
+                if let Some((ref drain, id)) = drain { drain.set_queued(id, Vec::new(), &handle); }
+                return;
+            }
+
+            // put all (time, data) pairs into a list ordered by time.
             input.for_each(|time, data| {
                 data.swap(&mut buffer1);
-                blocked.entry(time.retain())
-                       .or_insert(Vec::new())
-                       .extend(buffer1.drain(..))
-                });
+                blocked_at(&mut blocked, time).memory.append(&mut buffer1)
+            });
 
-            // scan each stashed element and see if it is time to process it.
-           for (time, data) in blocked.iter_mut() {
+            // scan each stashed element, oldest first, and see if it is time to process it.
+            for &mut (ref time, ref mut data) in blocked.iter_mut() {
                 // ok to process if no further updates less or equal to `time`.
                 if !handle.less_equal(time.time()) {
-                    // pop the data out of the list; we'll clean up the entry later.
-                    (*index).borrow_mut().count(data, &*logic2, &|t| (*valid)(t, time.time()), ident);
-                    output.session(time).give_iterator(data.drain(..).filter(|x| x.1 > 0));
+                    // process the records in memory, and then those of each spilled file in turn.
+                    loop {
+                        // pop the data out of the list; we'll clean up the entry later.
+                        with_hubs(&mut data.memory, &*index, &hubs, |x| (*logic1)(&x.0), |index, data| {
+                            index.count(data, &*logic2, &|t| (*valid)(t, time.time()), ident)
+                        });
+                        output.session(time).give_iterator(data.memory.drain(..).filter(|x| x.1 > 0));
+                        if !reload(data, &spill) { break; }
+                    }
                 }
             }
 
             // discard any data we processed up above.
-            blocked.retain(|_, data| data.len() > 0);
+            blocked.retain(|x| x.1.len() > 0);
+
+            if let Some(ref spill) = spill {
+                spill_latest(&mut blocked, spill, |_| false);
+            }
+
+            if let Some((ref drain, id)) = drain {
+                drain.set_queued(id, blocked.iter().map(|x| x.0.time().clone()).collect(), &handle);
+            }
         })
     }
 
-    fn propose(&self, stream: Stream<G, (Self::Prefix, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
-
-        let hash = self.hash.clone();
+    fn propose_with<G, W, C>(&self, stream: Stream<G, (P, W)>, pact: C) -> Stream<G, (P, Vec<V>, W)>
+    where
+        G: Scope<Timestamp=T>,
+        W: ExchangeData,
+        C: ParallelizationContract<T, (P, W)>,
+    {
         let logic1 = self.logic.clone();
         let logic2 = self.logic.clone();
         let valid = self.valid.clone();
         let handle = self.handle.clone();
-        let exch = Exchange::new(move |&(ref x,_)| (*hash)((*logic1)(x)));
 
         let index = self.index.clone();
+        let hubs = self.hubs.clone();
+        let chunk = self.chunk;
+        let drain = self.drain.as_ref().map(|drain| (drain.clone(), drain.register()));
+        let spill = self.spill.clone();
+        let cancelled = self.cancelled.clone();
 
         let mut buffer1 = Vec::new();
 
-        let mut blocked = HashMap::new();//vec![];
+        // blocked prefixes, with the extension after which to resume proposing, if any.
+        let mut blocked: Vec<(Capability<T>, Spilled<(P, Option<V>, W)>)> = Vec::new();
 
-        stream.unary(exch, "Propose", move |_,_| move |input, output| {
+        // the extensions recently proposed for each prefix, used to size batches of prefixes.
+        let mut per_prefix = 1;
+
+        stream.unary(pact, "Propose", move |_,_| move |input, output| {
+
+            // once cancelled, input and blocked prefixes are discarded, and capabilities dropped.
+            if cancelled.as_ref().map(|x| x.get()).unwrap_or(false) {
+                input.for_each(|_, data| { data.swap(&mut buffer1); buffer1.clear(); });
+                blocked.clear();
+                if let Some((ref drain, id)) = drain { drain.set_queued(id, Vec::new(), &handle); }
+                return;
+            }
 
             input.for_each(|time, data| {
                 data.swap(&mut buffer1);
-                blocked
-                    .entry(time.retain())
-                    .or_insert(Vec::new())
-                    // .extend(data.drain(..).map(|(p,s)| (p,vec![],s)))
-                    .push(::std::mem::replace(&mut buffer1, Vec::new()))
+                blocked_at(&mut blocked, time).memory.extend(buffer1.drain(..).map(|(p,w)| (p,None,w)))
             });
 
 
-            // scan each stashed element and see if it is time to process it.
-            for (time, data) in blocked.iter_mut() {
 
-                // ok to process if no further updates less or equal to `time`.
-                if !handle.less_equal(time.time()) {
+            // the effort is shared by all times, and spent on the oldest times first. it counts
+            // extensions proposed, and each prefix counts as at least one.
+            let mut effort = PROPOSE_EFFORT;
+
+            // the time whose prefixes are being proposed for, if effort ran out part way through.
+            let mut working = None;
+
+            // scan each stashed element, oldest first, and see if it is time to process it.
+            for &mut (ref time, ref mut data) in blocked.iter_mut() {
 
-                    let mut effort = 4096;
-                    while data.len() > 0 && effort > 0 {
-                        let mut list = data.pop().unwrap();
-                        effort = if list.len() > effort { 0 } else { effort - list.len() };
-
-                        let mut data = list.drain(..).map(|(p,s)| (p,vec![],s)).collect::<Vec<_>>();
-                        (*index).borrow_mut().propose(&mut data, &*logic2, &|t| (*valid)(t, time.time()));
-                        let mut session = output.session(&time);
-                        for x in data.drain(..) {
-                            if x.1.len() > 0 {
-                                session.give(x);
+                // defer work at `time` while operators downstream hold records they could process.
+                let deferred = drain.as_ref().map(|&(ref drain, id)| drain.downstream_of(id, time.time())).unwrap_or(false);
+
+                // ok to process if no further updates less or equal to `time`.
+                if !deferred && effort > 0 && !handle.less_equal(time.time()) {
+
+                    // spilled prefixes are read back a file at a time, once those in memory are done.
+                    let mut session = output.session(time);
+                    while effort > 0 && (!data.memory.is_empty() || reload(data, &spill)) {
+
+                        // take as many prefixes as the remaining effort allows, at recent rates.
+                        let take = ::std::cmp::min(::std::cmp::max(1, effort / per_prefix), data.memory.len());
+                        let start = data.memory.len() - take;
+
+                        // each prefix receives at most about `chunk` extensions, read from the 
+                        // index where the last left off, so that no one record is too large.
+                        let mut batch = data.memory.drain(start ..).map(|(p,r,s)| (p,r,vec![],s)).collect::<Vec<_>>();
+                        with_hubs(&mut batch, &*index, &hubs, |x| (*logic1)(&x.0), |index, data| {
+                            index.propose(data, &*logic2, &|t| (*valid)(t, time.time()), chunk)
+                        });
+
+                        let proposed = batch.iter().map(|x| x.2.len()).sum::<usize>();
+                        effort = effort.saturating_sub(::std::cmp::max(proposed, take));
+                        per_prefix = ::std::cmp::max(1, proposed / take);
+
+                        // prefixes with extensions remaining are proposed for again, next.
+                        for (prefix, resume, values, weight) in batch.drain(..) {
+                            if resume.is_some() {
+                                data.memory.push((prefix.clone(), resume, weight.clone()));
+                            }
+                            if !values.is_empty() {
+                                session.give((prefix, values, weight));
                             }
                         }
                     }
+                    working = Some(time.time().clone());
                 }
             }
 
-            blocked.retain(|_, data| data.len() > 0);
+            blocked.retain(|x| x.1.len() > 0);
+
+            if let Some(ref spill) = spill {
+                spill_latest(&mut blocked, spill, |time| working.as_ref() == Some(time.time()));
+            }
+
+            if let Some((ref drain, id)) = drain {
+                drain.set_queued(id, blocked.iter().map(|x| x.0.time().clone()).collect(), &handle);
+            }
     	})
     }
 
-    fn intersect(&self, stream: Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
-
-        let hash = self.hash.clone();
+    fn intersect_with<G, W, C>(&self, stream: Stream<G, (P, Vec<V>, W)>, pact: C) -> Stream<G, (P, Vec<V>, W)>
+    where
+        G: Scope<Timestamp=T>,
+        W: ExchangeData,
+        C: ParallelizationContract<T, (P, Vec<V>, W)>,
+    {
         let logic1 = self.logic.clone();
         let logic2 = self.logic.clone();
         let valid = self.valid.clone();
         let index = self.index.clone();
+        let hubs = self.hubs.clone();
         let handle = self.handle.clone();
+        let drain = self.drain.as_ref().map(|drain| (drain.clone(), drain.register()));
+        let spill = self.spill.clone();
+        let cancelled = self.cancelled.clone();
 
         let mut buffer = Vec::new();
-        let mut blocked = HashMap::new();
-        let exch = Exchange::new(move |&(ref x,_,_)| (*hash)((*logic1)(x)));
+        let mut blocked: Vec<(Capability<T>, Spilled<_>)> = Vec::new();
 
-        stream.unary(exch, "Intersect", move |_,_| move |input, output| {
+        stream.unary(pact, "Intersect", move |_,_| move |input, output| {
+
+            // once cancelled, input and blocked prefixes are discarded, and capabilities dropped.
+            if cancelled.as_ref().map(|x| x.get()).unwrap_or(false) {
+                input.for_each(|_, data| { data.swap(&mut buffer); buffer.clear(); });
+                blocked.clear();
+                if let Some((ref drain, id)) = drain { drain.set_queued(id, Vec::new(), &handle); }
+                return;
+            }
 
             input.for_each(|time, data| {
                 data.swap(&mut buffer);
-                blocked.entry(time.retain())
-                       .or_insert(Vec::new())
-                       .extend(buffer.drain(..))
+                blocked_at(&mut blocked, time).memory.append(&mut buffer)
             });
 
-            for (time, data) in blocked.iter_mut() {
+            for &mut (ref time, ref mut data) in blocked.iter_mut() {
 
                 // ok to process if no further updates less or equal to `time`.
                 if !handle.less_equal(time.time()) {
-                    (*index).borrow_mut().intersect(data, &*logic2, &|t| (*valid)(t, time.time()));
-                    output.session(&time).give_iterator(data.drain(..));
+                    // process the records in memory, and then those of each spilled file in turn.
+                    loop {
+                        with_hubs(&mut data.memory, &*index, &hubs, |x| (*logic1)(&x.0), |index, data| {
+                            index.intersect(data, &*logic2, &|t| (*valid)(t, time.time()))
+                        });
+                        output.session(time).give_iterator(data.memory.drain(..).filter(|x| !x.1.is_empty()));
+                        if !reload(data, &spill) { break; }
+                    }
                 }
             }
 
-            blocked.retain(|_, data| data.len() > 0);
+            blocked.retain(|x| x.1.len() > 0);
+
+            if let Some(ref spill) = spill {
+                spill_latest(&mut blocked, spill, |_| false);
+            }
+
+            if let Some((ref drain, id)) = drain {
+                drain.set_queued(id, blocked.iter().map(|x| x.0.time().clone()).collect(), &handle);
+            }
         })
     }
 }
 
+impl<K, V, G, P, L, H, F, W, B> StreamPrefixExtender<G, W> for Rc<IndexExtender<K, V, G::Timestamp, P, L, H, F, B>>
+where
+    K: Ord+Hash+Clone+ExchangeData,
+    V: Ord+Clone+ExchangeData,
+    G: Scope,
+    G::Timestamp: Timestamp+Ord+Clone,//+::std::hash::Hash+Ord,
+    P: ExchangeData+Debug,
+    L: Fn(&P)->K+'static,
+    H: Fn(K)->u64+'static,
+    F: Fn(&G::Timestamp, &G::Timestamp)->bool+'static,
+    W: ExchangeData,
+    B: IndexBackend<K, V, G::Timestamp>+'static,
+{
+    type Prefix = P;
+    type Extension = V;
+
+    fn count(&self, prefixes: Stream<G, (Self::Prefix, u64, u64, W)>, ident: u64) -> Stream<G, (Self::Prefix, u64, u64, W)> {
+        if self.replicated { return self.count_with(prefixes, ident, Pipeline); }
+        let route = self.router();
+        self.count_with(prefixes, ident, Exchange::new(move |(x,_,_,_)| route(x)))
+    }
+
+    fn propose(&self, stream: Stream<G, (Self::Prefix, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
+        if self.replicated { return self.propose_with(stream, Pipeline); }
+        let route = self.router();
+        self.propose_with(stream, Exchange::new(move |(x,_)| route(x)))
+    }
+
+    fn intersect(&self, stream: Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
+        if self.replicated { return self.intersect_with(stream, Pipeline); }
+        let route = self.router();
+        self.intersect_with(stream, Exchange::new(move |(x,_,_)| route(x)))
+    }
+
+    // prefixes are left in place if the index is replicated, and are otherwise placed by the 
+    // partitioner applied to their key, so long as the key is an attribute of the prefix and 
+    // no keys are spread round-robin.
+    fn route(&self) -> Option<Route> {
+        if self.replicated { return Some(Route::Anywhere); }
+        match (self.key, &self.hubs) {
+            (Some(key), &None) => Some(Route::Keyed { partition: self.partition, key }),
+            _ => None,
+        }
+    }
+
+    fn drain(&self) -> Option<Drain<G::Timestamp>> {
+        self.drain.clone()
+    }
+
+    fn count_local(&self, prefixes: Stream<G, (Self::Prefix, u64, u64, W)>, ident: u64) -> Stream<G, (Self::Prefix, u64, u64, W)> {
+        self.count_with(prefixes, ident, Pipeline)
+    }
+
+    fn propose_local(&self, stream: Stream<G, (Self::Prefix, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
+        self.propose_with(stream, Pipeline)
+    }
+
+    fn intersect_local(&self, stream: Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
+        self.intersect_with(stream, Pipeline)
+    }
+}
+
 
 mod merge_sorter {
 
@@ -353,9 +1144,9 @@ mod merge_sorter {
             let tail = list.len();
             unsafe { list.set_len(0); }
             VecQueue {
-                list: list,
+                list,
                 head: 0,
-                tail: tail,
+                tail,
             }
         }
         // could leak, if self.head != self.tail.
@@ -388,7 +1179,7 @@ mod merge_sorter {
     impl<D, K: Ord, F: Fn(&D)->K> MergeSorter<D, K, F> {
 
         #[inline]
-        pub fn new(logic: F) -> Self { MergeSorter { queue: Vec::new(), stash: Vec::new(), logic: logic, phant: ::std::marker::PhantomData } }
+        pub fn new(logic: F) -> Self { MergeSorter { queue: Vec::new(), stash: Vec::new(), logic, phant: ::std::marker::PhantomData } }
 
         #[inline]
         pub fn _empty(&mut self) -> Vec<D> {
@@ -410,11 +1201,11 @@ mod merge_sorter {
                 ::std::mem::replace(batch, self.stash.pop().unwrap())
             }
             else {
-                ::std::mem::replace(batch, Vec::new())
+                ::std::mem::take(batch)
             };
 
-            if batch.len() > 0 {
-                batch.sort_unstable_by(|x,y| (self.logic)(x).cmp(&(self.logic)(y)));
+            if !batch.is_empty() {
+                batch.sort_unstable_by_key(|x| (self.logic)(x));
                 self.queue.push(vec![batch]);
                 while self.queue.len() > 1 && (self.queue[self.queue.len()-1].len() >= self.queue[self.queue.len()-2].len() / 2) {
                     let list1 = self.queue.pop().unwrap();
@@ -470,7 +1261,7 @@ mod merge_sorter {
             // while we have valid data in each input, merge.
             while !head1.is_empty() && !head2.is_empty() {
 
-                while (result.capacity() - result.len()) > 0 && head1.len() > 0 && head2.len() > 0 {
+                while (result.capacity() - result.len()) > 0 && !head1.is_empty() && !head2.is_empty() {
 
                     // let cmp = {
                     //     let x = head1.peek();
@@ -514,7 +1305,7 @@ mod merge_sorter {
                 }
             }
 
-            if result.len() > 0 { output.push(result); }
+            if !result.is_empty() { output.push(result); }
             else if result.capacity() > 0 { self.stash.push(result); }
 
             if !head1.is_empty() {
@@ -0,0 +1,165 @@
+//! Counts of the work performed by the stages of join dataflows.
+//!
+//! Each call to `GenericJoin::extend_counted` is a stage, which extends a stream of prefixes by
+//! one attribute. For each timestamp, a stage records the prefixes it receives, the extensions
+//! each of its extenders proposes, and the extensions that survive each extender's intersection.
+//! The counts are produced as a stream of `(Stage, StageCounts)` at each timestamp, once the stage
+//! has completed its work at the timestamp, with one record for each stage at each worker.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use timely::Data;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Operator, Concatenate};
+
+/// Identifies a stage of a join dataflow.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stage {
+    /// The query, numbered in order of construction.
+    pub query: usize,
+    /// The relation whose updates the dataflow processes.
+    pub relation: usize,
+    /// The position of the stage in the dataflow.
+    pub stage: usize,
+}
+
+/// Counts of the work of a stage at a timestamp.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageCounts {
+    /// The number of prefixes received.
+    pub prefixes: u64,
+    /// For each extender, the number of extensions it proposed.
+    pub proposed: Vec<u64>,
+    /// For each extender, the number of extensions surviving its intersection, over all proposers.
+    pub intersected: Vec<u64>,
+    /// The number of extensions produced by the stage.
+    pub extensions: u64,
+}
+
+impl StageCounts {
+    // adds `count` to the `index`th element of `counts`.
+    fn add(counts: &mut Vec<u64>, index: usize, count: u64) {
+        while counts.len() <= index { counts.push(0); }
+        counts[index] += count;
+    }
+    // adds the counts of `other` to these.
+    fn absorb(&mut self, other: &StageCounts) {
+        self.prefixes += other.prefixes;
+        for (index, &count) in other.proposed.iter().enumerate() { StageCounts::add(&mut self.proposed, index, count); }
+        for (index, &count) in other.intersected.iter().enumerate() { StageCounts::add(&mut self.intersected, index, count); }
+        self.extensions += other.extensions;
+    }
+}
+
+/// Numbers the queries whose stages are counted.
+#[derive(Clone)]
+pub struct Fanout {
+    queries: Rc<Cell<usize>>,
+}
+
+impl Default for Fanout {
+    fn default() -> Self { Fanout::new() }
+}
+
+impl Fanout {
+
+    /// Allocates a new `Fanout`, with no queries.
+    pub fn new() -> Self { Fanout { queries: Rc::new(Cell::new(0)) } }
+
+    /// Allocates a number for a newly constructed query.
+    pub fn query(&self) -> usize {
+        let query = self.queries.get();
+        self.queries.set(query + 1);
+        query
+    }
+}
+
+/// What an observed stream carries, for the purposes of counting.
+#[derive(Copy, Clone, Debug)]
+pub enum Observe {
+    /// Prefixes received by the stage.
+    Prefixes,
+    /// Extensions proposed by the indicated extender.
+    Proposed(usize),
+    /// Extensions surviving intersection by the indicated extender.
+    Intersected(usize),
+    /// Extensions produced by the stage.
+    Extensions,
+}
+
+/// Counts records of streams at points in a stage, passing the streams through unchanged.
+pub trait Observer<G: Scope> {
+    /// Adds `count` of the records of `stream` to the count `what` indicates.
+    fn observe<D: Data, F: Fn(&D)->u64+'static>(&self, stream: &Stream<G, D>, what: Observe, count: F) -> Stream<G, D>;
+}
+
+// counts nothing, and adds no operators.
+impl<G: Scope> Observer<G> for () {
+    fn observe<D: Data, F: Fn(&D)->u64+'static>(&self, stream: &Stream<G, D>, _what: Observe, _count: F) -> Stream<G, D> {
+        stream.clone()
+    }
+}
+
+/// Counts the records of the streams of one stage, as a stream of counts for each timestamp.
+pub struct StageObserver<G: Scope> {
+    stage: Stage,
+    counts: RefCell<Vec<Stream<G, (Stage, StageCounts)>>>,
+}
+
+impl<G: Scope> StageObserver<G> where G::Timestamp: Hash {
+
+    /// Allocates an observer counting the work of `stage`.
+    pub fn new(stage: Stage) -> Self {
+        StageObserver { stage, counts: RefCell::new(Vec::new()) }
+    }
+
+    /// The counts of the stage at each timestamp, once the stage has completed its work there.
+    ///
+    /// There is a record for each timestamp at which any stream observed so far had records.
+    pub fn counts(&self, scope: &G) -> Stream<G, (Stage, StageCounts)> {
+        let mut totals = HashMap::new();
+        let mut buffer = Vec::new();
+        scope.concatenate(self.counts.borrow().clone()).unary_notify(Pipeline, "FanoutCounts", vec![], move |input, output, notificator| {
+            input.for_each(|time, data| {
+                data.swap(&mut buffer);
+                let total = totals.entry(time.time().clone()).or_insert_with(HashMap::new);
+                for (stage, counts) in buffer.drain(..) {
+                    total.entry(stage).or_insert_with(StageCounts::default).absorb(&counts);
+                }
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time,_,_| {
+                if let Some(total) = totals.remove(time.time()) {
+                    output.session(&time).give_iterator(total.into_iter());
+                }
+            });
+        })
+    }
+}
+
+impl<G: Scope> Observer<G> for StageObserver<G> where G::Timestamp: Hash {
+    fn observe<D: Data, F: Fn(&D)->u64+'static>(&self, stream: &Stream<G, D>, what: Observe, count: F) -> Stream<G, D> {
+        let stage = self.stage;
+        let mut buffer = Vec::new();
+        let counts = stream.unary(Pipeline, "Fanout", move |_,_| move |input, output| {
+            input.for_each(|time, data| {
+                data.swap(&mut buffer);
+                let total = buffer.drain(..).map(|x| count(&x)).sum::<u64>();
+                let mut counts = StageCounts::default();
+                match what {
+                    Observe::Prefixes => counts.prefixes += total,
+                    Observe::Proposed(index) => StageCounts::add(&mut counts.proposed, index, total),
+                    Observe::Intersected(index) => StageCounts::add(&mut counts.intersected, index, total),
+                    Observe::Extensions => counts.extensions += total,
+                }
+                output.session(&time).give((stage, counts));
+            });
+        });
+        self.counts.borrow_mut().push(counts);
+        stream.clone()
+    }
+}
@@ -0,0 +1,343 @@
+//! Limits on the work queries may perform for each timestamp.
+//!
+//! A single update can produce enormous numbers of intermediate prefixes, for example an edge
+//! between two high-degree nodes in a dense motif. A `Guard` bounds the prefixes each query may
+//! produce, and optionally the time it may take, for each timestamp at each worker. When a query
+//! exceeds these limits at a timestamp, the guard acts as its `GuardAction` indicates and records
+//! a `GuardEvent` describing the update most responsible.
+//!
+//! Guarded queries hold their results for each timestamp until the timestamp is complete, so that
+//! they can be suppressed if the limits are exceeded after some have been produced. The limits are
+//! enforced at each worker, but the decision is shared: a worker exceeding them broadcasts a trip
+//! to all workers, and every worker acts on the trip before it releases results for the timestamp.
+//! The work a worker has already done for the timestamp is not stopped by trips at other workers.
+
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use timely::Data;
+use timely::progress::Timestamp;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Operator, Map, Concatenate, Broadcast};
+
+use motif::{Node, Edge};
+use extender::Drain;
+
+/// The response to a query exceeding its limits at a timestamp.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GuardAction {
+    /// Stop the query, discarding its results at the timestamp and all later timestamps.
+    ///
+    /// Results at earlier timestamps are released as usual, once complete.
+    Abort,
+    /// Stop work for the timestamp, discarding the query's results at the timestamp.
+    ///
+    /// The extensions of the query are discarded from the moment of the trip, and so the results
+    /// `GuardEvent` records are only those produced before it.
+    Skip,
+    /// Complete the work for the timestamp, but report only the number of results.
+    ///
+    /// The extensions of the query continue, and so the results `GuardEvent` records are all of 
+    /// the query's results at the timestamp and worker.
+    CountOnly,
+}
+
+/// A report of a query exceeding its limits at a timestamp, at one worker.
+///
+/// A worker acting on a trip from another worker records an event of its own, describing its share
+/// of the work at the timestamp.
+#[derive(Clone, Debug)]
+pub struct GuardEvent<T> {
+    /// The query, numbered in order of construction.
+    pub query: usize,
+    /// The relation of the query whose update was being processed, at the worker that tripped.
+    pub relation: usize,
+    /// The timestamp at which the limits were exceeded.
+    pub time: T,
+    /// The update responsible for the most prefixes at the worker that tripped, when the limits 
+    /// were exceeded.
+    pub update: Edge,
+    /// The number of prefixes produced for `update` at this worker.
+    pub update_prefixes: usize,
+    /// The number of prefixes produced for all updates at this worker.
+    pub prefixes: usize,
+    /// The time spent on the timestamp.
+    pub elapsed: Duration,
+    /// The action taken.
+    pub action: GuardAction,
+    /// The accumulated weight of results discarded or counted at this worker, once complete.
+    ///
+    /// This is the weight of all results only with `GuardAction::CountOnly`; other actions stop
+    /// work, and record the results produced before they did.
+    pub results: i64,
+}
+
+/// Limits on the prefixes and time each query may use for each timestamp, at each worker.
+///
+/// Each guarded query reports any events to the same log, available through `events`.
+pub struct Guard<T> {
+    max_prefixes: usize,
+    max_elapsed: Option<Duration>,
+    action: GuardAction,
+    queries: Rc<Cell<usize>>,
+    events: Rc<RefCell<Vec<GuardEvent<T>>>>,
+}
+
+impl<T> Clone for Guard<T> {
+    fn clone(&self) -> Self {
+        Guard {
+            max_prefixes: self.max_prefixes,
+            max_elapsed: self.max_elapsed,
+            action: self.action,
+            queries: self.queries.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Guard<T> {
+
+    /// Limits each query to `max_prefixes` intermediate prefixes per timestamp, enforced by `action`.
+    pub fn new(max_prefixes: usize, action: GuardAction) -> Self {
+        Guard {
+            max_prefixes,
+            max_elapsed: None,
+            action,
+            queries: Rc::new(Cell::new(0)),
+            events: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Additionally limits each query to `max_elapsed` time per timestamp.
+    ///
+    /// The time is measured from when the first prefixes for the timestamp reach the guard.
+    pub fn with_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// The events recorded by all guarded queries.
+    pub fn events(&self) -> Vec<GuardEvent<T>> {
+        self.events.borrow().clone()
+    }
+
+    /// Allocates state for a newly constructed query.
+    pub fn query(&self) -> QueryGuard<T> {
+        let query = self.queries.get();
+        self.queries.set(query + 1);
+        QueryGuard {
+            guard: self.clone(),
+            query,
+            state: Rc::new(RefCell::new(QueryState { aborted: Vec::new(), times: HashMap::new() })),
+            drain: None,
+        }
+    }
+}
+
+/// The state of a guarded query, shared by the operators of its dataflow.
+pub struct QueryGuard<T> {
+    guard: Guard<T>,
+    query: usize,
+    state: Rc<RefCell<QueryState<T>>>,
+    drain: Option<Drain<T>>,
+}
+
+struct QueryState<T> {
+    /// Times at which the query tripped with `GuardAction::Abort`.
+    aborted: Vec<T>,
+    times: HashMap<T, TimeState>,
+}
+
+impl<T: Timestamp> QueryState<T> {
+    // indicates whether the query aborted at `time` or an earlier time.
+    fn aborted_at(&self, time: &T) -> bool {
+        self.aborted.iter().any(|x| x.less_equal(time))
+    }
+}
+
+struct TimeState {
+    start: Instant,
+    prefixes: usize,
+    updates: HashMap<Edge, usize>,
+    /// The position of this timestamp's event, if the limits have been exceeded.
+    event: Option<usize>,
+}
+
+impl<T: Timestamp+Hash> QueryGuard<T> {
+
+    /// Reports the results held by the query through `drain`, if any.
+    ///
+    /// The results are recorded downstream of the `Drain`'s position when `results` is called.
+    pub fn with_drain(mut self, drain: Option<Drain<T>>) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    /// Counts the extensions of a stage of `relation`'s dataflow, and discards them if required.
+    ///
+    /// The `update` function extracts from each prefix the update that produced it. The second 
+    /// stream reports each time at which this worker exceeds the limits, as the relation and the 
+    /// update most responsible; the trips of all stages are provided to `results`.
+    pub fn stage<G, P, U>(&self, stream: &Stream<G, (P, Vec<Node>, i32)>, relation: usize, update: U) -> (Stream<G, (P, Vec<Node>, i32)>, Stream<G, (usize, Edge)>)
+    where
+        G: Scope<Timestamp=T>,
+        P: Data,
+        U: Fn(&P)->Edge+'static,
+    {
+        let guard = self.guard.clone();
+        let query = self.query;
+        let state = self.state.clone();
+        let mut buffer = Vec::new();
+
+        // extensions are produced as `Ok`, and trips as `Err`, and then separated.
+        let tagged = stream.unary(Pipeline, "GuardStage", move |_,_| move |input, output| {
+            input.for_each(|time, data| {
+                data.swap(&mut buffer);
+                let mut state = state.borrow_mut();
+                let state = &mut *state;
+                if state.aborted_at(time.time()) { buffer.clear(); return; }
+
+                let times = &mut state.times;
+                let entry = times.entry(time.time().clone()).or_insert_with(|| TimeState {
+                    start: Instant::now(),
+                    prefixes: 0,
+                    updates: HashMap::new(),
+                    event: None,
+                });
+
+                for record in buffer.iter() {
+                    entry.prefixes += record.1.len();
+                    *entry.updates.entry(update(&record.0)).or_insert(0) += record.1.len();
+                }
+
+                let elapsed = entry.start.elapsed();
+                let exceeded = entry.prefixes > guard.max_prefixes || guard.max_elapsed.map(|x| elapsed > x).unwrap_or(false);
+                let mut session = output.session(&time);
+                if exceeded && entry.event.is_none() {
+                    let (update, update_prefixes) = entry.updates.iter().max_by_key(|x| (x.1, x.0)).map(|(u,c)| (*u, *c)).unwrap_or(((0, 0), 0));
+                    session.give(Err((relation, update)));
+                    let mut events = guard.events.borrow_mut();
+                    entry.event = Some(events.len());
+                    events.push(GuardEvent {
+                        query,
+                        relation,
+                        time: time.time().clone(),
+                        update,
+                        update_prefixes,
+                        prefixes: entry.prefixes,
+                        elapsed,
+                        action: guard.action,
+                        results: 0,
+                    });
+                }
+
+                match (entry.event.is_some(), guard.action) {
+                    (true, GuardAction::Abort) => {
+                        if !state.aborted_at(time.time()) { state.aborted.push(time.time().clone()); }
+                        buffer.clear();
+                    },
+                    (true, GuardAction::Skip) => { buffer.clear(); },
+                    _ => { session.give_iterator(buffer.drain(..).map(Ok)); },
+                }
+            });
+        });
+
+        (tagged.flat_map(|x| x.ok()), tagged.flat_map(|x| x.err()))
+    }
+
+    /// Holds the query's results for each timestamp until it is complete, and then releases them
+    /// if the limits were not exceeded at any worker.
+    ///
+    /// The `trips` are those reported by each `stage` of the query, and are broadcast to all workers.
+    pub fn results<G, D>(&self, stream: &Stream<G, (D, i32)>, trips: &[Stream<G, (usize, Edge)>]) -> Stream<G, (D, i32)>
+    where
+        G: Scope<Timestamp=T>,
+        D: Data,
+    {
+        let guard = self.guard.clone();
+        let query = self.query;
+        let state = self.state.clone();
+        let drain = self.drain.as_ref().map(|drain| (drain.clone(), drain.register()));
+        let mut pending = HashMap::new();
+        let mut tripped = HashMap::new();
+        let mut buffer = Vec::new();
+        let mut trip_buffer = Vec::new();
+
+        let trips = stream.scope().concatenate(trips.to_vec()).broadcast();
+        stream.binary_notify(&trips, Pipeline, Pipeline, "GuardResults", vec![], move |input, trips, output, notificator| {
+
+            // the first trip of each time, from any worker.
+            trips.for_each(|time, data| {
+                data.swap(&mut trip_buffer);
+                if let Some(trip) = trip_buffer.drain(..).next() {
+                    tripped.entry(time.time().clone()).or_insert(trip);
+                }
+                notificator.notify_at(time.retain());
+            });
+
+            input.for_each(|time, data| {
+                data.swap(&mut buffer);
+                let state = state.borrow();
+                let event = state.times.get(time.time()).and_then(|x| x.event);
+                if let Some(event) = event {
+                    guard.events.borrow_mut()[event].results += buffer.drain(..).map(|x| x.1 as i64).sum::<i64>();
+                }
+                else if state.aborted_at(time.time()) {
+                    buffer.clear();
+                }
+                else {
+                    pending.entry(time.time().clone()).or_insert(Vec::new()).append(&mut buffer);
+                }
+                notificator.notify_at(time.retain());
+            });
+
+            notificator.for_each(|time,_,_| {
+                let mut results = pending.remove(time.time()).unwrap_or(Vec::new());
+                let mut state = state.borrow_mut();
+                let local = state.times.remove(time.time());
+                let mut event = local.as_ref().and_then(|x| x.event);
+
+                // a trip at another worker is recorded as an event of this worker, and acted upon.
+                if let (None, Some((relation, update))) = (event, tripped.remove(time.time())) {
+                    let mut events = guard.events.borrow_mut();
+                    event = Some(events.len());
+                    events.push(GuardEvent {
+                        query,
+                        relation,
+                        time: time.time().clone(),
+                        update,
+                        update_prefixes: local.as_ref().and_then(|x| x.updates.get(&update).cloned()).unwrap_or(0),
+                        prefixes: local.as_ref().map(|x| x.prefixes).unwrap_or(0),
+                        elapsed: local.as_ref().map(|x| x.start.elapsed()).unwrap_or(Duration::from_secs(0)),
+                        action: guard.action,
+                        results: 0,
+                    });
+                    if guard.action == GuardAction::Abort && !state.aborted_at(time.time()) {
+                        state.aborted.push(time.time().clone());
+                    }
+                }
+                tripped.remove(time.time());
+
+                if let Some(event) = event {
+                    guard.events.borrow_mut()[event].results += results.drain(..).map(|x| x.1 as i64).sum::<i64>();
+                }
+                else if !state.aborted_at(time.time()) {
+                    output.session(&time).give_vec(&mut results);
+                }
+            });
+
+            // the state of times neither input can still deliver is discarded, including that of
+            // times which produced no results or trips, and so were never notified.
+            let (frontier1, frontier2) = (notificator.frontier(0), notificator.frontier(1));
+            state.borrow_mut().times.retain(|time, _| frontier1.iter().chain(frontier2.iter()).any(|x| x.less_equal(time)));
+
+            if let Some((ref drain, id)) = drain {
+                drain.set_held(id, pending.keys().cloned().collect());
+            }
+        })
+    }
+}
@@ -1,53 +1,178 @@
 use advance;
+use IndexBackend;
 
+use std::fmt;
+use std::error::Error;
 use std::hash::Hash;
-use std::collections::HashMap;
+use std::mem::size_of;
+
+use intersection::Lanes;
 
-use self::edge_list_neu::EdgeList;
 use self::compact::CompactIndex;
+use self::edges::Edges;
 use self::unsorted::Unsorted;
 
+pub use self::bitmap::BitmapValue;
+pub use self::dense::{DenseKey, Layout};
+
+/// Number of values above which a key's values are also held in a `Bitmap`.
+///
+/// Below this degree galloping through the sorted values is cheap enough, and the bitmap
+/// would only cost memory. Above it, intersection becomes a membership probe per proposal.
+const BITMAP_THRESHOLD: usize = 1 << 12;
+
+/// Factor by which a list of updates with a bitmap must outnumber the proposals intersected
+/// with it before they are probed in the bitmap rather than merged with the list.
+///
+/// Each probe that succeeds must still search the updates for its count, and so probing only
+/// wins when most proposals are absent and the updates are long enough that searching for the
+/// rest costs less than merging.
+const BITMAP_RATIO: usize = 16;
+
 /// A multiversion multimap from `Key` to `Val`.
 ///
 /// An `Index` represents a multiversion `(Key, Val)` relation keyed on the first field. 
-/// It presently assumes that the keys are dense, and so uses a `Vec<State>` to maintain 
-/// per-key state. This could be generalized (and may need to be) to index structures
-/// such as e.g. `HashMap`.
+/// By default keys may be sparse: compacted data are located by searching a sorted list of
+/// keys, and committed updates are found through a `HashMap` from keys to update lists.
+///
+/// When keys are dense integers, such as compact vertex identifiers, `Index::dense` instead
+/// locates both by the key's offset within the worker's partition, using per-key vectors 
+/// rather than searches or hashing. This requires that the key be routed to worker 
+/// `key % peers`, as the `|k| k as u64` hash function does.
+///
+/// Keys with many values additionally record them in a compressed bitmap, so that `intersect` 
+/// can test membership directly rather than search through long sorted lists. The choice is
+/// made per key, for keys whose degree exceeds `BITMAP_THRESHOLD`, and is automatic for `u8`,
+/// `u16`, and `u32` values. Other values use sorted lists only, unless they implement 
+/// `BitmapValue` and the index is built with `with_bitmaps`.
+///
+/// When enabled with `with_simd`, sorted lists of values are intersected with the vectorized
+/// kernels of the `intersection` crate, which requires values that implement `Lanes`.
 pub struct Index<Key: Ord+Hash, Val: Ord, T> {
     /// Optionally, a pair of (key, end) and (val) lists, representing compacted accumulation.
     // compact: (Vec<(Key, usize)>, Vec<u32>),
     compact: CompactIndex<Key, Val>,
     /// An index of committed but un-compacted updates.
-    edges: HashMap<Key, EdgeList<Val>>,
+    edges: Edges<Key, Val>,
     /// A sorted list of un-committed updates.
     // diffs: Vec<(Key, u32, T, i32)>,
     diffs: Unsorted<Key, Val, T>,
+    /// Optionally, a bound on approximate bytes beyond which `update` fails.
+    memory_limit: Option<usize>,
+    /// A running count of approximate bytes, maintained as the index changes.
+    bytes: usize,
+    /// Optionally, the placement of values in bitmaps for high-degree keys.
+    bitmaps: Option<fn(&Val)->u32>,
+    /// Optionally, a view of lists of values as `u32` values, for vectorized intersection.
+    lanes: Option<for<'a> fn(&'a [Val])->Option<&'a [u32]>>,
+}
+
+/// Sizes of the parts of an `Index`, as reported by `Index::stats`.
+///
+/// Byte counts are approximate: they account for allocated capacity of the underlying vectors
+/// and maps, but not for allocator overhead.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IndexStats {
+    /// Number of keys with compacted values.
+    pub compact_keys: usize,
+    /// Number of compacted values.
+    pub compact_values: usize,
+    /// Approximate bytes used by compacted values, including bitmaps.
+    pub compact_bytes: usize,
+    /// Number of keys with committed but un-compacted updates.
+    pub edge_keys: usize,
+    /// Number of committed but un-compacted updates.
+    pub edge_values: usize,
+    /// Number of sorted runs across all committed update lists.
+    pub edge_runs: usize,
+    /// Approximate bytes used by committed updates, including bitmaps.
+    pub edge_bytes: usize,
+    /// Number of un-committed updates.
+    pub diffs: usize,
+    /// Approximate bytes used by un-committed updates.
+    pub diff_bytes: usize,
+}
+
+impl IndexStats {
+    /// Approximate bytes used by the whole index.
+    pub fn bytes(&self) -> usize {
+        self.compact_bytes + self.edge_bytes + self.diff_bytes
+    }
+    /// Number of keys with compacted or committed values, at least the number of keys in the index.
+    ///
+    /// A key with both compacted and committed values is counted twice.
+    pub fn keys(&self) -> usize {
+        self.compact_keys + self.edge_keys
+    }
+    /// Number of values and updates held, at least the number of values in the index.
+    pub fn values(&self) -> usize {
+        self.compact_values + self.edge_values + self.diffs
+    }
 }
 
+/// The error returned by `Index::update` when updates would exceed the index's memory limit.
+///
+/// The updates are not applied, and remain with the caller.
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryLimitExceeded {
+    /// The configured limit, in bytes.
+    pub limit: usize,
+    /// The approximate bytes the index would use with the updates applied.
+    pub bytes: usize,
+}
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "index memory limit exceeded: {} bytes required, limit is {} bytes", self.bytes, self.limit)
+    }
+}
+
+impl Error for MemoryLimitExceeded { }
+
 mod compact {
 
+    use std::collections::HashMap;
+    use std::mem::size_of;
+
     use super::advance;
+    use super::bitmap::Bitmap;
+    use super::dense::Layout;
 
+    /// Compacted values, grouped by key.
+    ///
+    /// With a dense `layout` keys are not recorded, and `ranges` instead holds the bounds 
+    /// of each key's values at the key's offset.
     pub struct CompactIndex<K, V> {
         keys: Vec<(K, usize)>,
         vals: Vec<V>,
+        /// Bitmaps for high-degree keys, indexed by their position in `keys` or `ranges`.
+        bitmaps: HashMap<usize, Bitmap<V>>,
+        layout: Option<Layout<K>>,
+        ranges: Vec<(usize, usize)>,
     }
 
     impl<K: Ord, V: Ord> CompactIndex<K, V> {
 
-        /// Allocates a new `CompactIndex`.
-        pub fn new() -> Self {
+        /// Allocates a new `CompactIndex`, with an optional dense layout for keys.
+        pub fn new(layout: Option<Layout<K>>) -> Self {
             CompactIndex {
                 keys: Vec::new(),
                 vals: Vec::new(),
+                bitmaps: HashMap::new(),
+                layout,
+                ranges: Vec::new(),
             }
         }
 
         /// Load a `CompactIndex` from an ordered sequence of key-value pairs.
-        pub fn load<I: Iterator<Item = (K, V)>>(&mut self, length: usize, iterator: I) {
+        ///
+        /// High-degree keys also have bitmaps, if `place` locates values in them.
+        pub fn load<I: Iterator<Item = (K, V)>>(&mut self, length: usize, iterator: I, place: Option<fn(&V)->u32>) {
 
             self.keys.clear();
             self.vals.clear();
+            self.bitmaps.clear();
+            self.ranges.clear();
             self.vals.reserve(length);
 
             for (key, val) in iterator {
@@ -60,13 +185,77 @@ mod compact {
                     self.keys[idx-1].1 = self.vals.len();
                 }
             }
+
+            // build bitmaps for keys with many values. a bitmap records only presence, so we
+            // skip keys whose values repeat, as their multiplicities matter to intersection.
+            // with a dense layout, we also record each key's bounds at its offset.
+            for position in 0 .. self.keys.len() {
+                let lower = if position == 0 { 0 } else { self.keys[position-1].1 };
+                let upper = self.keys[position].1;
+                let slot = match self.layout {
+                    Some(ref layout) => {
+                        let offset = layout.offset(&self.keys[position].0);
+                        if self.ranges.len() <= offset {
+                            self.ranges.resize(offset + 1, (0, 0));
+                        }
+                        self.ranges[offset] = (lower, upper);
+                        offset
+                    },
+                    None => position,
+                };
+                let values = &self.vals[lower .. upper];
+                if let Some(place) = place {
+                    if values.len() > super::BITMAP_THRESHOLD && values.windows(2).all(|x| x[0] < x[1]) {
+                        self.bitmaps.insert(slot, Bitmap::from_values(values.iter(), place));
+                    }
+                }
+            }
+
+            // keys are not needed once their bounds are recorded by offset.
+            if self.layout.is_some() {
+                self.keys = Vec::new();
+            }
+        }
+
+        /// Reports the number of keys and values.
+        pub fn len(&self) -> (usize, usize) {
+            let keys = match self.layout {
+                Some(_) => self.ranges.iter().filter(|x| x.0 < x.1).count(),
+                None => self.keys.len(),
+            };
+            (keys, self.vals.len())
+        }
+
+        /// Approximate bytes allocated for keys, values, and bitmaps.
+        pub fn bytes(&self) -> usize {
+            self.keys.capacity() * size_of::<(K, usize)>() +
+            self.vals.capacity() * size_of::<V>() +
+            self.ranges.capacity() * size_of::<(usize, usize)>() +
+            self.bitmaps.capacity() * (size_of::<usize>() + size_of::<Bitmap<V>>()) +
+            self.bitmaps.values().map(|x| x.bytes()).sum::<usize>()
         }
 
         /// Reveal the slice for `key` starting from (and updating) `key_cursor`.
         #[inline(always)]
         pub fn values_from<'a>(&'a self, key: &K, key_cursor: &mut usize) -> &'a [V] {
+            self.entry_from(key, key_cursor).0
+        }
 
-            if *key_cursor < self.keys.len() {
+        /// Reveal the slice and any bitmap for `key` starting from (and updating) `key_cursor`.
+        ///
+        /// A bitmap is only present for keys with many values, and contains exactly the values
+        /// in the returned slice. With a dense layout the cursor is not needed, and is ignored.
+        #[inline(always)]
+        pub fn entry_from<'a>(&'a self, key: &K, key_cursor: &mut usize) -> (&'a [V], Option<&'a Bitmap<V>>) {
+
+            if let Some(ref layout) = self.layout {
+                let offset = layout.offset(key);
+                match self.ranges.get(offset) {
+                    Some(&(lower, upper)) if lower < upper => (&self.vals[lower .. upper], self.bitmaps.get(&offset)),
+                    _ => (&[], None),
+                }
+            }
+            else if *key_cursor < self.keys.len() {
 
                 *key_cursor += advance(&self.keys[*key_cursor..], |x| &x.0 < key);
 
@@ -76,19 +265,22 @@ mod compact {
 
                     assert!(lower < upper);
 
+                    let bitmap = self.bitmaps.get(&*key_cursor);
                     *key_cursor += 1;
-                    &self.vals[lower .. upper]                
+                    (&self.vals[lower .. upper], bitmap)
                 }
-                else { &[] }
+                else { (&[], None) }
             }
-            else { &[] }
+            else { (&[], None) }
         }
     }
 }
 
 mod edge_list_neu {
 
-    use super::advance;
+    use std::mem::size_of;
+
+    use super::bitmap::Bitmap;
 
     /// A LSM-style list of updates.
     ///
@@ -105,11 +297,17 @@ mod edge_list_neu {
     ///
     /// The `count` field tracks the sum of all updates in `values`, for constant-time 
     /// reference when required.
+    ///
+    /// The `bitmap` field, when present, holds exactly the values of a single consolidated 
+    /// run, and lets `intersect` rule out absent values with a membership probe. It is only
+    /// built for long lists whose values have a placement in bitmaps, which the methods that
+    /// consolidate runs take as `place`, and is discarded as soon as new updates are pushed.
     pub struct EdgeList<V: Ord> {
         bounds: Vec<usize>,
         values: Vec<(V, i32)>,
         effort: u32,
         count: i32,     // accumulated diffs; could be negative
+        bitmap: Option<Bitmap<V>>,
     }
 
     impl<V: Ord> EdgeList<V> {
@@ -122,12 +320,28 @@ mod edge_list_neu {
                 values: Vec::new(),
                 effort: 0,
                 count: 0,
+                bitmap: None,
             } 
         }
 
         #[inline(always)]
         pub fn count(&self) -> i32 { self.count }
 
+        /// Reports the number of updates and the number of sorted runs they form.
+        pub fn len(&self) -> (usize, usize) {
+            let runs = if self.values.is_empty() { 0 } else { self.bounds.len() + 1 };
+            (self.values.len(), runs)
+        }
+
+        /// Approximate bytes allocated for updates, bounds, and any bitmap.
+        ///
+        /// This does not include the size of the `EdgeList` itself.
+        pub fn heap_bytes(&self) -> usize {
+            self.bounds.capacity() * size_of::<usize>() +
+            self.values.capacity() * size_of::<(V, i32)>() +
+            self.bitmap.as_ref().map(|x| x.bytes()).unwrap_or(0)
+        }
+
         // The next methods are, annoyingly, in support of pushing updates into the LSM.
         // Because insertion is a bit interactive, with tests on timestamps and setting 
         // of weights for moved records, this is not supplied as an iterator to use for 
@@ -143,6 +357,7 @@ mod edge_list_neu {
         pub fn push(&mut self, update: (V, i32)) {
             self.count += update.1;
             self.values.push(update);
+            self.bitmap = None;
         }
 
         /// Seal an ordered sequence of pushed updates.
@@ -151,14 +366,14 @@ mod edge_list_neu {
         /// for reflection on whether the most recent sorted run of updates is 
         /// large enough that we should merge it with prior runs. 
         #[inline(always)]
-        pub fn seal_from(&mut self, position: usize) {
+        pub fn seal_from(&mut self, position: usize, place: Option<fn(&V)->u32>) {
 
             // only if values have been pushed.
             if self.values.len() > position {
 
                 // we will push `position` only if there are already values, and 
                 // the new run is shorter than half the second most recent run.
-                let prev_run = position - self.bounds.last().map(|&x| x).unwrap_or(0);
+                let prev_run = position - self.bounds.last().copied().unwrap_or(0);
                 if self.values.len() - position < prev_run / 2 {
                     self.bounds.push(position);
                 }
@@ -180,22 +395,29 @@ mod edge_list_neu {
                         self.bounds = Vec::new();
                     }
 
-                    self.consolidate_tail();
+                    self.consolidate_tail(place);
                 }
             }
         }
 
+        /// Merges all sorted runs, so that `proposals` reflects every update.
         #[inline(always)]
-        pub fn proposals(&mut self) -> &[(V, i32)] {
-            if self.bounds.len() > 0 {
+        pub fn consolidate(&mut self, place: Option<fn(&V)->u32>) {
+            if !self.bounds.is_empty() {
                 self.bounds = Vec::new();
-                self.consolidate_tail();
+                self.consolidate_tail(place);
             }
+        }
+
+        /// The consolidated updates, following a call to `consolidate`.
+        #[inline(always)]
+        pub fn proposals(&self) -> &[(V, i32)] {
+            debug_assert!(self.bounds.is_empty());
             &self.values[..]
         }
 
-        fn consolidate_tail(&mut self) {
-            let bound = self.bounds.last().map(|&x| x).unwrap_or(0);
+        fn consolidate_tail(&mut self, place: Option<fn(&V)->u32>) {
+            let bound = self.bounds.last().copied().unwrap_or(0);
             self.values[bound ..].sort_by(|x,y| x.0.cmp(&y.0));
 
             let mut cursor = bound;            
@@ -216,6 +438,12 @@ mod edge_list_neu {
 
             self.values.truncate(cursor);
 
+            // a single long run is worth a bitmap, to speed up intersection.
+            if let Some(place) = place {
+                if self.bounds.is_empty() && self.values.len() > super::BITMAP_THRESHOLD {
+                    self.bitmap = Some(Bitmap::from_values(self.values.iter().map(|x| &x.0), place));
+                }
+            }
         }
 
         /// Indicate that a certain amount of effort will be expended.
@@ -224,12 +452,12 @@ mod edge_list_neu {
         /// that is about to be done. If a great deal of work will be done, it may make sense to
         /// consolidate the edge list to simplify that work.
         #[inline(always)]
-        pub fn expend(&mut self, effort: u32) {
-            if self.bounds.len() > 0 {
+        pub fn expend(&mut self, effort: u32, place: Option<fn(&V)->u32>) {
+            if !self.bounds.is_empty() {
                 self.effort += effort;
                 if (self.effort as usize) > self.values.len() {
                     self.bounds = Vec::new();
-                    self.consolidate_tail();
+                    self.consolidate_tail(place);
                 }
                 self.effort = 0;
             }
@@ -241,11 +469,28 @@ mod edge_list_neu {
         /// This method is used to assist with intersection testing, by reporting accumulated
         /// counts for each element of the supplied `values`.
         #[inline(never)]
-        pub fn intersect(&self, values: &[V], temp: &mut Vec<i32>) {
+        pub fn intersect(&self, values: &[V], temp: &mut [i32]) {
 
             assert!(temp.len() == values.len());
             assert!(temp.iter().all(|&x| x == 0));
-            
+
+            // with a bitmap, we only need to search for values that are present. each search
+            // costs a logarithmic factor, and so we only probe when the updates greatly outnumber
+            // the values, and otherwise merge. repeated values are matched only once, as in the merge.
+            if let Some(ref bitmap) = self.bitmap {
+                if values.len() * super::BITMAP_RATIO < self.values.len() {
+                    for index in 0 .. values.len() {
+                        let value = &values[index];
+                        if (index == 0 || &values[index-1] != value) && bitmap.contains(value) {
+                            if let Ok(position) = self.values.binary_search_by(|x| x.0.cmp(value)) {
+                                temp[index] += self.values[position].1;
+                            }
+                        }
+                    }
+                    return;
+                }
+            }
+
             let mut slice = &self.values[..];
 
             // for each bound, process the subsequent sorted run.
@@ -258,34 +503,405 @@ mod edge_list_neu {
             EdgeList::intersect_helper(values, slice, &mut temp[..]);
         }
 
-        // to simplify things, this accumulates updates 
+        // to simplify things, this accumulates updates. a repeated value in `source` is only
+        // matched once, and takes the accumulated count of all matching updates in the run.
         fn intersect_helper(source: &[V], updates: &[(V, i32)], counts: &mut [i32]) {
+            ::intersection::intersect_by(source, updates, |x| &x.0, |s_cursor, mut u_cursor| {
+                if s_cursor == 0 || source[s_cursor-1] != source[s_cursor] {
+                    while updates.get(u_cursor).map(|x| &x.0) == Some(&source[s_cursor]) {
+                        counts[s_cursor] += updates[u_cursor].1;
+                        u_cursor += 1;
+                    }
+                }
+            });
+        }
+    }
+}
 
-            use std::cmp::Ordering;
+mod dense {
 
-            let mut s_cursor = 0;
-            let mut u_cursor = 0;
+    /// Keys that enumerate densely, as compact integer identifiers do.
+    pub trait DenseKey {
+        /// The position of the key in the enumeration.
+        fn dense_index(&self) -> usize;
+    }
 
-            // merge by galloping
-            while s_cursor < source.len() && u_cursor < updates.len() {
-                match source[s_cursor].cmp(&updates[u_cursor].0) {
-                    Ordering::Less => {
-                        let step = 1 + advance(&source[(s_cursor+1)..], |x| x < &updates[u_cursor].0);
-                        debug_assert!(step > 0);
-                        s_cursor += step;
-                    },
-                    Ordering::Equal => {
-                        counts[s_cursor] += updates[u_cursor].1;
-                        s_cursor += 1;
-                        u_cursor += 1;
+    impl DenseKey for u8 { #[inline(always)] fn dense_index(&self) -> usize { *self as usize } }
+    impl DenseKey for u16 { #[inline(always)] fn dense_index(&self) -> usize { *self as usize } }
+    impl DenseKey for u32 { #[inline(always)] fn dense_index(&self) -> usize { *self as usize } }
+    impl DenseKey for u64 { #[inline(always)] fn dense_index(&self) -> usize { *self as usize } }
+    impl DenseKey for usize { #[inline(always)] fn dense_index(&self) -> usize { *self } }
+
+    /// The positions of one worker's keys in dense storage.
+    ///
+    /// Worker `index` of `peers` is presumed to hold exactly those keys whose dense index is 
+    /// `index` modulo `peers`, and locates each at its dense index divided by `peers`.
+    pub struct Layout<K> {
+        position: fn(&K)->usize,
+        index: usize,
+        peers: usize,
+    }
+
+    impl<K> Clone for Layout<K> {
+        fn clone(&self) -> Self { *self }
+    }
+
+    impl<K> Copy for Layout<K> { }
+
+    impl<K: DenseKey> Layout<K> {
+        /// Describes the keys of worker `index` out of `peers`.
+        pub fn new(index: usize, peers: usize) -> Self {
+            assert!(index < peers);
+            Layout { position: <K as DenseKey>::dense_index, index, peers }
+        }
+    }
+
+    impl<K> Layout<K> {
+        /// The offset of `key` in this worker's dense storage.
+        ///
+        /// This method panics if the key does not belong to the worker, as it would otherwise
+        /// collide with the key that does.
+        #[inline(always)]
+        pub fn offset(&self, key: &K) -> usize {
+            let position = (self.position)(key);
+            assert!(position % self.peers == self.index, "key routed to the wrong worker for a dense index");
+            position / self.peers
+        }
+    }
+}
+
+mod edges {
+
+    use std::hash::Hash;
+    use std::mem::size_of;
+    use std::collections::HashMap;
+
+    use super::edge_list_neu::EdgeList;
+    use super::dense::Layout;
+
+    /// Committed update lists for each key.
+    ///
+    /// Sparse keys are found by hashing, whereas dense keys are found at their offsets. Dense
+    /// lists are boxed, so that keys without updates cost only a pointer.
+    pub enum Edges<K, V: Ord> {
+        Sparse(HashMap<K, EdgeList<V>>),
+        Dense(Layout<K>, Vec<Option<Box<EdgeList<V>>>>),
+    }
+
+    impl<K: Hash+Eq+Clone, V: Ord> Edges<K, V> {
+
+        /// Allocates new empty `Edges`, dense if a layout is supplied.
+        pub fn new(layout: Option<Layout<K>>) -> Self {
+            match layout {
+                Some(layout) => Edges::Dense(layout, Vec::new()),
+                None => Edges::Sparse(HashMap::new()),
+            }
+        }
+
+        #[inline(always)]
+        pub fn get(&self, key: &K) -> Option<&EdgeList<V>> {
+            match *self {
+                Edges::Sparse(ref map) => map.get(key),
+                Edges::Dense(ref layout, ref lists) => {
+                    lists.get(layout.offset(key)).and_then(|x| x.as_ref()).map(|x| &**x)
+                },
+            }
+        }
+
+        #[inline(always)]
+        pub fn get_mut(&mut self, key: &K) -> Option<&mut EdgeList<V>> {
+            match *self {
+                Edges::Sparse(ref mut map) => map.get_mut(key),
+                Edges::Dense(ref layout, ref mut lists) => {
+                    lists.get_mut(layout.offset(key)).and_then(|x| x.as_mut()).map(|x| &mut **x)
+                },
+            }
+        }
+
+        /// Reports the number of keys, updates, and sorted runs, and approximate bytes allocated.
+        pub fn stats(&self) -> (usize, usize, usize, usize) {
+            let (mut keys, mut values, mut runs, mut bytes) = (0, 0, 0, 0);
+            {
+                let mut record = |list: &EdgeList<V>| {
+                    let (list_values, list_runs) = list.len();
+                    keys += 1;
+                    values += list_values;
+                    runs += list_runs;
+                    bytes += list.heap_bytes();
+                };
+                match *self {
+                    Edges::Sparse(ref map) => {
+                        for list in map.values() { record(list); }
                     },
-                    Ordering::Greater => {
-                        let step = 1 + advance(&updates[(u_cursor+1)..], |x| x.0 < source[s_cursor]);
-                        debug_assert!(step > 0);
-                        u_cursor += step;
+                    Edges::Dense(_, ref lists) => {
+                        for list in lists.iter().filter_map(|x| x.as_ref()) { record(list); }
                     },
                 }
             }
+            bytes += match *self {
+                // a hash map entry holds a key, a list, and a hash.
+                Edges::Sparse(ref map) => map.capacity() * (size_of::<K>() + size_of::<EdgeList<V>>() + size_of::<u64>()),
+                Edges::Dense(_, ref lists) => lists.capacity() * size_of::<Option<Box<EdgeList<V>>>>() + keys * size_of::<EdgeList<V>>(),
+            };
+            (keys, values, runs, bytes)
+        }
+
+        /// Approximate bytes each key with an update list adds, beyond the list's own allocations.
+        pub fn entry_bytes(&self) -> usize {
+            match *self {
+                Edges::Sparse(_) => size_of::<K>() + size_of::<EdgeList<V>>() + size_of::<u64>(),
+                Edges::Dense(_, _) => size_of::<Option<Box<EdgeList<V>>>>() + size_of::<EdgeList<V>>(),
+            }
+        }
+
+        /// The update list for `key`, created if it does not yet exist.
+        pub fn entry(&mut self, key: &K) -> &mut EdgeList<V> {
+            match *self {
+                Edges::Sparse(ref mut map) => map.entry(key.clone()).or_insert(EdgeList::new()),
+                Edges::Dense(ref layout, ref mut lists) => {
+                    let offset = layout.offset(key);
+                    while lists.len() <= offset {
+                        lists.push(None);
+                    }
+                    lists[offset].get_or_insert_with(|| Box::new(EdgeList::new()))
+                },
+            }
+        }
+    }
+}
+
+mod bitmap {
+
+    use std::any::{Any, TypeId};
+
+    /// Values that can be recorded in a `Bitmap`, for indices that opt in with `Index::with_bitmaps`.
+    ///
+    /// The index must be injective, as the bitmap records presence of indices only.
+    pub trait BitmapValue {
+        /// The position of the value in a bitmap.
+        fn bitmap_index(&self) -> u32;
+    }
+
+    impl BitmapValue for u8 { #[inline(always)] fn bitmap_index(&self) -> u32 { *self as u32 } }
+    impl BitmapValue for u16 { #[inline(always)] fn bitmap_index(&self) -> u32 { *self as u32 } }
+    impl BitmapValue for u32 { #[inline(always)] fn bitmap_index(&self) -> u32 { *self } }
+
+    /// The placement of values in bitmaps, if `V` is one of the integer types placed by default.
+    pub fn placement<V: 'static>() -> Option<fn(&V)->u32> {
+        let id = TypeId::of::<V>();
+        if id == TypeId::of::<u32>() || id == TypeId::of::<u16>() || id == TypeId::of::<u8>() {
+            Some(place::<V>)
+        }
+        else {
+            None
+        }
+    }
+
+    // places a value of one of the types `placement` accepts.
+    #[inline(always)]
+    fn place<V: 'static>(value: &V) -> u32 {
+        let value = value as &dyn Any;
+        if let Some(x) = value.downcast_ref::<u32>() { x.bitmap_index() }
+        else if let Some(x) = value.downcast_ref::<u16>() { x.bitmap_index() }
+        else if let Some(x) = value.downcast_ref::<u8>() { x.bitmap_index() }
+        else { unreachable!("values are placed only if `placement` accepts their type") }
+    }
+
+    /// Number of elements above which a container switches from a sorted array to bits.
+    const ARRAY_LIMIT: usize = 4096;
+
+    /// The low sixteen bits of values sharing the same high sixteen bits.
+    enum Container {
+        /// A sorted list of distinct low bits, for sparse containers.
+        Array(Vec<u16>),
+        /// A dense bit vector of 1 << 16 bits.
+        Bits(Vec<u64>),
+    }
+
+    impl Container {
+        /// Approximate bytes allocated for the container's contents.
+        fn bytes(&self) -> usize {
+            match *self {
+                Container::Array(ref list) => list.capacity() * 2,
+                Container::Bits(ref bits) => bits.capacity() * 8,
+            }
+        }
+
+        #[inline(always)]
+        fn contains(&self, low: u16) -> bool {
+            match *self {
+                Container::Array(ref list) => list.binary_search(&low).is_ok(),
+                Container::Bits(ref bits) => bits[(low >> 6) as usize] & (1 << (low & 63)) != 0,
+            }
+        }
+
+        /// Inserts `low` and reports whether it was newly added.
+        fn insert(&mut self, low: u16) -> bool {
+            let added = match *self {
+                Container::Array(ref mut list) => {
+                    // values often arrive in order, so check the end first.
+                    if list.last().map(|&x| x < low).unwrap_or(true) {
+                        list.push(low);
+                        true
+                    }
+                    else {
+                        match list.binary_search(&low) {
+                            Ok(_) => false,
+                            Err(position) => { list.insert(position, low); true },
+                        }
+                    }
+                },
+                Container::Bits(ref mut bits) => {
+                    let word = &mut bits[(low >> 6) as usize];
+                    let mask = 1 << (low & 63);
+                    let added = *word & mask == 0;
+                    *word |= mask;
+                    added
+                },
+            };
+
+            // convert large arrays to bits, which are then smaller.
+            let convert = match *self {
+                Container::Array(ref list) => list.len() > ARRAY_LIMIT,
+                Container::Bits(_) => false,
+            };
+            if convert {
+                let mut bits = vec![0u64; 1 << 10];
+                if let Container::Array(ref list) = *self {
+                    for &low in list.iter() {
+                        bits[(low >> 6) as usize] |= 1 << (low & 63);
+                    }
+                }
+                *self = Container::Bits(bits);
+            }
+
+            added
+        }
+    }
+
+    /// A compressed set of values placed at `u32` positions, in the style of roaring bitmaps.
+    ///
+    /// Positions are grouped by their high sixteen bits, and the low sixteen bits of each group
+    /// are stored either as a sorted array or as a bit vector, whichever is smaller. This 
+    /// gives constant-time membership tests at a cost of at most two bytes per value, plus
+    /// a bit more for very sparse sets.
+    pub struct Bitmap<V> {
+        highs: Vec<u16>,
+        containers: Vec<Container>,
+        place: fn(&V)->u32,
+    }
+
+    impl<V> Bitmap<V> {
+
+        /// Allocates a new empty `Bitmap`, placing values by `place`.
+        pub fn new(place: fn(&V)->u32) -> Self {
+            Bitmap {
+                highs: Vec::new(),
+                containers: Vec::new(),
+                place,
+            }
+        }
+
+        /// Builds a `Bitmap` from values, most efficiently if they are presented in order.
+        pub fn from_values<'a, I: Iterator<Item=&'a V>>(values: I, place: fn(&V)->u32) -> Self where V: 'a {
+            let mut bitmap = Bitmap::new(place);
+            for value in values {
+                bitmap.insert(value);
+            }
+            bitmap
+        }
+
+        /// Inserts `value`, reporting whether it was newly added.
+        pub fn insert(&mut self, value: &V) -> bool {
+            let value = (self.place)(value);
+            let high = (value >> 16) as u16;
+            let position = if self.highs.last() == Some(&high) { self.highs.len() - 1 } else {
+                match self.highs.binary_search(&high) {
+                    Ok(position) => position,
+                    Err(position) => {
+                        self.highs.insert(position, high);
+                        self.containers.insert(position, Container::Array(Vec::new()));
+                        position
+                    }
+                }
+            };
+            self.containers[position].insert(value as u16)
+        }
+
+        /// Approximate bytes allocated for the bitmap.
+        pub fn bytes(&self) -> usize {
+            self.highs.capacity() * 2 +
+            self.containers.capacity() * ::std::mem::size_of::<Container>() +
+            self.containers.iter().map(|x| x.bytes()).sum::<usize>()
+        }
+
+        /// Reports whether `value` is present.
+        #[inline(always)]
+        pub fn contains(&self, value: &V) -> bool {
+            let value = (self.place)(value);
+            match self.highs.binary_search(&((value >> 16) as u16)) {
+                Ok(position) => self.containers[position].contains(value as u16),
+                Err(_) => false,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+
+        use super::*;
+
+        fn place(value: &u32) -> u32 { *value }
+
+        #[test]
+        fn placement_of_integers() {
+            assert_eq!(placement::<u32>().map(|place| place(&70_000)), Some(70_000));
+            assert_eq!(placement::<u16>().map(|place| place(&60_000)), Some(60_000));
+            assert_eq!(placement::<u8>().map(|place| place(&200)), Some(200));
+            assert!(placement::<u64>().is_none());
+            assert!(placement::<(u32, u32)>().is_none());
+        }
+
+        #[test]
+        fn insert_and_contains() {
+            // values out of order, spread across several high sixteen bits.
+            let values = [5, 1 << 20, 3, 70_000, 65_535, 65_536, u32::MAX, 0];
+            let mut bitmap = Bitmap::new(place);
+            for value in values.iter() {
+                assert!(bitmap.insert(value), "{} newly added", value);
+            }
+            for value in values.iter() {
+                assert!(!bitmap.insert(value), "{} already present", value);
+                assert!(bitmap.contains(value), "{} present", value);
+            }
+            for absent in [1, 4, 6, 65_537, (1 << 20) + 1, u32::MAX - 1].iter() {
+                assert!(!bitmap.contains(absent), "{} absent", absent);
+            }
+        }
+
+        #[test]
+        fn dense_containers() {
+            // more than `ARRAY_LIMIT` values sharing high bits switch to a bit vector.
+            let evens = (0 .. 3 * ARRAY_LIMIT as u32).map(|x| 2 * x).collect::<Vec<_>>();
+            let bitmap = Bitmap::from_values(evens.iter(), place);
+            match bitmap.containers[0] {
+                Container::Bits(_) => { },
+                Container::Array(_) => panic!("expected a bit vector"),
+            }
+            for x in 0 .. 6 * ARRAY_LIMIT as u32 {
+                assert_eq!(bitmap.contains(&x), x % 2 == 0, "{}", x);
+            }
+            assert!(!bitmap.contains(&(1 << 16)));
+
+            // the switch happens in the middle of out of order inserts, too.
+            let mut bitmap = Bitmap::new(place);
+            for x in (0 .. 2 * ARRAY_LIMIT as u32).rev() {
+                assert!(bitmap.insert(&(3 * x)));
+            }
+            for x in 0 .. 2 * ARRAY_LIMIT as u32 {
+                assert!(!bitmap.insert(&(3 * x)));
+                assert!(!bitmap.contains(&(3 * x + 1)));
+            }
         }
     }
 }
@@ -315,23 +931,96 @@ mod unsorted {
             self.updates.extend(iterator.map(|((k,v),d)| (k, v, time.clone(), d)));
             self.updates.sort_by(|x,y| (&x.0, &x.1).cmp(&(&y.0, &y.1)));
 
-            if self.min_time == None || self.min_time.as_ref().unwrap() > &time {
+            if self.min_time.is_none() || self.min_time.as_ref().unwrap() > &time {
                 self.min_time = Some(time);
             }
         }
     }
 }
 
-impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
+impl<Key: Ord+Hash+Clone+DenseKey, Val: Ord+Clone+'static, T: Ord+Clone> Index<Key, Val, T> {
+
+    /// Allocates a new empty index for the dense keys of worker `index` out of `peers`.
+    ///
+    /// Keys are located by their offset in per-worker vectors, and must be routed to worker
+    /// `key % peers`. A key routed elsewhere causes a panic, rather than an incorrect answer.
+    pub fn dense(index: usize, peers: usize) -> Self {
+        Index::with_layout(Some(Layout::new(index, peers)))
+    }
+}
+
+impl<Key: Ord+Hash+Clone, Val: Ord+Clone+BitmapValue, T: Ord+Clone> Index<Key, Val, T> {
+
+    /// Records the values of high-degree keys in bitmaps, to speed up intersection.
+    ///
+    /// Indices of `u8`, `u16`, and `u32` values do this without being asked; other values
+    /// opt in by implementing `BitmapValue`. This should be called before the index is initialized or updated, as values already 
+    /// present only gain bitmaps when they are next compacted.
+    pub fn with_bitmaps(mut self) -> Self {
+        self.bitmaps = Some(<Val as BitmapValue>::bitmap_index);
+        self
+    }
+}
+
+impl<Key: Ord+Hash+Clone, Val: Ord+Clone+Lanes, T: Ord+Clone> Index<Key, Val, T> {
+
+    /// Intersects lists of values with vectorized kernels, where their layout allows it.
+    pub fn with_simd(mut self) -> Self {
+        self.lanes = Some(<Val as Lanes>::as_u32s);
+        self
+    }
+}
+
+impl<Key: Ord+Hash+Clone, Val: Ord+Clone+'static, T: Ord+Clone> Index<Key, Val, T> {
 
     /// Allocates a new empty index.
     pub fn new() -> Self { 
+        Index::with_layout(None)
+    }
+
+    /// Allocates a new empty index, with dense storage if a layout is supplied.
+    fn with_layout(layout: Option<Layout<Key>>) -> Self {
         Index { 
-            compact: CompactIndex::new(),
-            edges: HashMap::new(), 
+            compact: CompactIndex::new(layout),
+            edges: Edges::new(layout), 
             diffs: Unsorted::new(), 
+            memory_limit: None,
+            bytes: 0,
+            bitmaps: bitmap::placement::<Val>(),
+            lanes: None,
         } 
     }
+}
+
+impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
+
+    /// Sets or clears a bound on the approximate bytes used by the index.
+    ///
+    /// With a limit set, `update` fails rather than introduce updates that would take the index
+    /// past the limit. The limit is checked against a running count of bytes, which `update`, 
+    /// `merge_to`, and `initialize` adjust for the data they change. It does not account for
+    /// the spare capacity of maps from keys to their updates, and so may be somewhat lower than
+    /// the bytes reported by `stats`.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Reports the sizes of the parts of the index.
+    pub fn stats(&self) -> IndexStats {
+        let (compact_keys, compact_values) = self.compact.len();
+        let (edge_keys, edge_values, edge_runs, edge_bytes) = self.edges.stats();
+        IndexStats {
+            compact_keys,
+            compact_values,
+            compact_bytes: self.compact.bytes(),
+            edge_keys,
+            edge_values,
+            edge_runs,
+            edge_bytes,
+            diffs: self.diffs.updates.len(),
+            diff_bytes: self.diffs.updates.capacity() * size_of::<(Key, Val, T, i32)>(),
+        }
+    }
 
     /// Updates entries of `data` to reflect counts in the index.
     ///
@@ -340,17 +1029,17 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
     /// happens if the counts proposed here would be smaller than what is currently recorded in the
     /// tuple.
     #[inline(never)]
-    pub fn count<P,K,Valid,W>(&mut self, data: &mut Vec<(P, u64, u64, W)>, func: &K, _valid: &Valid, ident: u64) 
+    pub fn count<P,K,Valid,W>(&mut self, data: &mut [(P, u64, u64, W)], func: &K, _valid: &Valid, ident: u64) 
     where K:Fn(&P)->Key, Valid:Fn(&T)->bool {
 
         // sort data by key, to share work for the same key.
-        data.sort_by(|x,y| func(&x.0).cmp(&(func(&y.0))));
+        data.sort_by_key(|x| func(&x.0));
 
         // cursors into `self.compact` and `self.diffs`.
         let mut c_cursor = 0;
         let mut d_cursor = 0;
 
-        let possible_diffs = self.diffs.min_time.as_ref().map(|t| _valid(t)).unwrap_or(false);
+        let possible_diffs = self.diffs.min_time.as_ref().map(_valid).unwrap_or(false);
 
         let mut index = 0;
         while index < data.len() {
@@ -383,19 +1072,26 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
         }
     }
 
-    /// Proposes extensions for prefixes based on the index.
+    /// Proposes the next extensions for each prefix, resuming after each record's second field.
+    ///
+    /// Each source of values for a key is sorted, and so the proposals are found by seeking each
+    /// to the resume point and taking at most `limit` values from it, without assembling all of
+    /// the key's values. Values are complete up to the least last value of a source with more
+    /// values remaining, which becomes the new resume point.
     #[inline(never)]
-    pub fn propose<P, K, Valid,W>(&mut self, data: &mut Vec<(P, Vec<Val>, W)>, func: &K, valid: &Valid) 
+    pub fn propose<P, K, Valid, W>(&mut self, data: &mut [(P, Option<Val>, Vec<Val>, W)], func: &K, valid: &Valid, limit: usize) 
     where K:Fn(&P)->Key, Valid:Fn(&T)->bool {
 
+        // without any values a proposal could never make progress.
+        assert!(limit > 0, "proposals must be limited to at least one value");
+
         // sorting allows us to re-use computation for the same key, and simplifies the searching 
         // of self.compact and self.diffs.
-        data.sort_unstable_by(|x,y| func(&x.0).cmp(&(func(&y.0))));
+        data.sort_unstable_by_key(|x| func(&x.0));
 
         // fingers into compacted data and uncommited updates.
         let mut offset_cursor = 0;
         let mut diffs_cursor = 0;
-        // let mut diffs = &self.diffs[..];
 
         // temporary array to stage proposals
         let mut proposals = Vec::<(Val, i32)>::new();
@@ -404,47 +1100,71 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
         let mut index = 0;  
         while index < data.len() {
 
-            // for each key, we (i) determine the proposals and then (ii) supply them to each 
-            // entry of `data` with the same key.
+            // for each key, we (i) locate the sources of values and then (ii) take the next
+            // values from each for every entry of `data` with the same key.
 
             let key = func(&data[index].0);
-            proposals.clear();
 
-            // (ia): incorporate updates from `self.compact`.
-            let values = self.compact.values_from(&key, &mut offset_cursor);
-            proposals.extend(values.iter().map(|v| (v.clone(), 1)));
+            // (ia): values from `self.compact`.
+            let compact = self.compact.values_from(&key, &mut offset_cursor);
+
+            // (ib): updates from `self.edges`, which are consolidated if they are not already.
+            let place = self.bitmaps;
+            let edges: &[(Val, i32)] = match self.edges.get_mut(&key) {
+                Some(entry) => {
+                    let before = entry.heap_bytes();
+                    entry.consolidate(place);
+                    self.bytes = (self.bytes + entry.heap_bytes()).saturating_sub(before);
+                    entry.proposals()
+                },
+                None => &[],
+            };
+
+            // (ic): updates from `self.diffs`, at any time.
+            let diffs = self.diffs.values_from(&key, &mut diffs_cursor);
+
+            // (ii): we may have multiple records with the same key, propose for them all.
+            while index < data.len() && func(&data[index].0) == key {
+
+                let (compact, more_c) = window(compact, |x| x, data[index].1.as_ref(), limit);
+                let (edges, more_e) = window(edges, |x| &x.0, data[index].1.as_ref(), limit);
+                let (diffs, more_d) = window(diffs, |x| &x.1, data[index].1.as_ref(), limit);
 
-            // (ib): incorporate updates from `self.edges`.
-            self.edges.get_mut(&key).map(|entry| proposals.extend_from_slice(entry.proposals()));
+                // values are complete up to the least last value of any source with more values.
+                let mut resume = None;
+                for &(more, last) in [(more_c, compact.last()), (more_e, edges.last().map(|x| &x.0)), (more_d, diffs.last().map(|x| &x.1))].iter() {
+                    if more && (resume.is_none() || last < resume) {
+                        resume = last;
+                    }
+                }
 
-            // (ic): incorporate updates from `self.diffs`.
-            let values = self.diffs.values_from(&key, &mut diffs_cursor);
-            for &(ref _key, ref val, ref time, wgt) in values.iter() {
-                if valid(time) {
-                    proposals.push((val.clone(), wgt));
+                proposals.clear();
+                proposals.extend(compact.iter().map(|v| (v.clone(), 1)));
+                proposals.extend_from_slice(edges);
+                for &(ref _key, ref val, ref time, wgt) in diffs.iter() {
+                    if valid(time) {
+                        proposals.push((val.clone(), wgt));
+                    }
                 }
-            }
 
-            // (id): consolidate all the counts that we added in, keep positive counts.
-            if proposals.len() > 0 {
-                proposals.sort_by(|x,y| x.0.cmp(&y.0));
-                for cursor in 0 .. proposals.len() - 1 {
-                    if proposals[cursor].0 == proposals[cursor + 1].0 {
-                        proposals[cursor + 1].1 += proposals[cursor].1;
-                        proposals[cursor].1 = 0;
+                // consolidate all the counts that we added in, keep positive counts.
+                if !proposals.is_empty() {
+                    proposals.sort_by(|x,y| x.0.cmp(&y.0));
+                    for cursor in 0 .. proposals.len() - 1 {
+                        if proposals[cursor].0 == proposals[cursor + 1].0 {
+                            proposals[cursor + 1].1 += proposals[cursor].1;
+                            proposals[cursor].1 = 0;
+                        }
                     }
+                    proposals.retain(|x| x.1 > 0 && resume.map(|r| &x.0 <= r).unwrap_or(true));
                 }
-                proposals.retain(|x| x.1 > 0);
-            }
-        
 
-            // (ii): we may have multiple records with the same key, propose for them all.
-            while index < data.len() && func(&data[index].0) == key {
                 for &(ref val, cnt) in &proposals {
                     for _ in 0 .. cnt {
-                        data[index].1.push(val.clone());
+                        data[index].2.push(val.clone());
                     }
                 }
+                data[index].1 = resume.cloned();
                 index += 1;
             }
         }
@@ -452,11 +1172,11 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
 
     /// Restricts extensions for prefixes to those found in the index.
     #[inline(never)]
-    pub fn intersect<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Vec<Val>, W)>, func: &F, valid: &Valid) 
+    pub fn intersect<P, F, Valid, W>(&mut self, data: &mut [(P, Vec<Val>, W)], func: &F, valid: &Valid) 
     where F: Fn(&P)->Key, Valid: Fn(&T)->bool {
 
         // sorting data by key allows us to re-use some work / compact representations.
-        data.sort_unstable_by(|x,y| func(&x.0).cmp(&(func(&y.0))));
+        data.sort_unstable_by_key(|x| func(&x.0));
 
         // counts for each value to validate
         let mut temp = Vec::new();
@@ -480,11 +1200,17 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
             }
 
             // (i) position `self.compact` cursor so that we can re-use it.
-            let compact_slice = self.compact.values_from(&key, &mut offset_cursor);
+            let (compact_slice, compact_bitmap) = self.compact.entry_from(&key, &mut offset_cursor);
 
             // (ii) prepare non-compact updates. if our effort level is large, consolidate. 
             let mut entry = self.edges.get_mut(&key);
-            entry.as_mut().map(|x| x.expend(effort as u32));
+            let place = self.bitmaps;
+            if let Some(ref mut entry) = entry {
+                let before = entry.heap_bytes();
+                entry.expend(effort as u32, place);
+                self.bytes = (self.bytes + entry.heap_bytes()).saturating_sub(before);
+            }
+            let lanes = self.lanes;
 
             // (iii) position `self.diffs` cursor so that we can re-use it.
             let diffs_slice = self.diffs.values_from(&key, &mut diffs_cursor);
@@ -505,36 +1231,53 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
                 temp.resize(proposals.len(), 0);
 
                 // (ia) update `temp` counts based on `self.edges[key]`, if it exists.
-                entry.as_mut().map(|x| x.intersect(proposals, &mut temp));
-
-                // (ib, ic) update `temp` counts based on `self.compact` and `self.diffs`.
-                let mut c_cursor = 0;
-                let mut d_cursor = 0;
-
-                // walk proposals linearly (could gallop, if we felt strongly enough).
-                for (proposal, count) in proposals.iter().zip(temp.iter_mut()) {
-
-                    // move c_cursor to where `proposal` would start ..
-                    c_cursor += advance(&compact_slice[c_cursor..], |x| x < proposal);
-                    while compact_slice.get(c_cursor) == Some(proposal) {
-                        *count += 1;
-                        c_cursor += 1;
+                if let Some(x) = entry.as_mut() { x.intersect(proposals, &mut temp) }
+
+                // (ib, ic) update `temp` counts based on `self.compact` and `self.diffs`. in each case
+                // a repeated proposal is only matched once, as the first takes all matching counts.
+                {
+                    let proposals = &proposals[..];
+                    let first = |index: usize| index == 0 || proposals[index-1] != proposals[index];
+
+                    if let Some(bitmap) = compact_bitmap {
+                        // high-degree keys have distinct values, and we need only probe.
+                        for (index, proposal) in proposals.iter().enumerate() {
+                            if first(index) && bitmap.contains(proposal) {
+                                temp[index] += 1;
+                            }
+                        }
                     }
-
-                    // move d_cursor to where `proposal` would start ..
-                    d_cursor += advance(&diffs_slice[d_cursor..], |x| &x.1 < proposal);
-                    while diffs_slice.get(d_cursor).map(|x| &x.1) == Some(proposal) {
-                        if valid(&diffs_slice[d_cursor].2) {
-                            *count += diffs_slice[d_cursor].3;
+                    else {
+                        let report = |index: usize, mut c_cursor: usize| {
+                            if first(index) {
+                                while compact_slice.get(c_cursor) == Some(&proposals[index]) {
+                                    temp[index] += 1;
+                                    c_cursor += 1;
+                                }
+                            }
+                        };
+                        match lanes.map(|lanes| (lanes(proposals), lanes(compact_slice))) {
+                            Some((Some(p32), Some(c32))) => ::intersection::intersect_u32s(p32, c32, report),
+                            _ => ::intersection::intersect(proposals, compact_slice, report),
                         }
-                        d_cursor += 1;
                     }
+
+                    ::intersection::intersect_by(proposals, diffs_slice, |x| &x.1, |index, mut d_cursor| {
+                        if first(index) {
+                            while diffs_slice.get(d_cursor).map(|x| &x.1) == Some(&proposals[index]) {
+                                if valid(&diffs_slice[d_cursor].2) {
+                                    temp[index] += diffs_slice[d_cursor].3;
+                                }
+                                d_cursor += 1;
+                            }
+                        }
+                    });
                 }
 
                 // (ii) remove elements whose count is not strictly positive.
                 let mut cursor = 0;
-                for i in 0 .. temp.len() {
-                    if temp[i] > 0 {
+                for (i, &count) in temp.iter().enumerate() {
+                    if count > 0 {
                         proposals.swap(cursor, i);
                         cursor += 1;
                     }
@@ -559,12 +1302,21 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
     #[inline(never)]
     pub fn merge_to(&mut self, time: &T) {
 
+        // the net change in bytes of the lists we touch. committed updates are removed from
+        // `self.diffs`, but its allocation remains for the updates to come.
+        let mut grown = 0;
+        let mut shrunk = 0;
+
         let mut index = 0;
         while index < self.diffs.updates.len() {
 
             let key_index = index;
-            let entry = self.edges.entry(self.diffs.updates[key_index].0.clone()).or_insert(EdgeList::new());
+            if self.edges.get(&self.diffs.updates[key_index].0).is_none() {
+                grown += self.edges.entry_bytes();
+            }
+            let entry = self.edges.entry(&self.diffs.updates[key_index].0);
             let prior_position = entry.position();
+            shrunk += entry.heap_bytes();
 
             while self.diffs.updates.get(index).map(|x| &x.0) == self.diffs.updates.get(key_index).map(|x| &x.0) {
                 if self.diffs.updates[index].2.le(time) {
@@ -574,27 +1326,143 @@ impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> Index<Key, Val, T> {
                 index += 1;
             }
 
-            entry.seal_from(prior_position);
+            entry.seal_from(prior_position, self.bitmaps);
+            grown += entry.heap_bytes();
         }
 
         // remove committed updates
         self.diffs.updates.retain(|x| x.3 != 0);
+        self.bytes = (self.bytes + grown).saturating_sub(shrunk);
         self.diffs.min_time = self.diffs.updates.iter().map(|x| x.2.clone()).min();
     }
 
     /// Introduces a collection of updates at various times.
     /// 
     /// These updates will now be reflected in all queries against the index, at or after the 
-    /// indicated logical time.
+    /// indicated logical time. If the index has a memory limit and the updates would exceed it,
+    /// the updates are left in `updates` and an error is returned.
     #[inline(never)]
-    pub fn update(&mut self, time: T, updates: &mut Vec<((Key, Val), i32)>) {
+    pub fn update(&mut self, time: T, updates: &mut Vec<((Key, Val), i32)>) -> Result<(), MemoryLimitExceeded> {
+        // grow to an explicit capacity, so that the check charges what the allocation will.
+        let (length, before) = (self.diffs.updates.len(), self.diffs.updates.capacity());
+        let capacity = if length + updates.len() > before { ::std::cmp::max(2 * before, length + updates.len()) } else { before };
+        let grown = (capacity - before) * size_of::<(Key, Val, T, i32)>();
+        if let Some(limit) = self.memory_limit {
+            let bytes = self.bytes + grown;
+            if bytes > limit {
+                return Err(MemoryLimitExceeded { limit, bytes });
+            }
+        }
+        self.diffs.updates.reserve_exact(capacity - length);
         self.diffs.extend(time, updates.drain(..));
+        self.bytes += (self.diffs.updates.capacity() - before) * size_of::<(Key, Val, T, i32)>();
+        Ok(())
     }
 
     /// Sets an initial collection of positive counts, which we can compact.
     #[inline(never)]
     pub fn initialize(&mut self, initial: &mut Vec<Vec<(Key, Val)>>) {
         let length = initial.iter().map(|x| x.len()).sum();
-        self.compact.load(length, initial.drain(..).flat_map(|x| x.into_iter()));
+        let before = self.compact.bytes();
+        self.compact.load(length, initial.drain(..).flat_map(|x| x.into_iter()), self.bitmaps);
+        self.bytes = (self.bytes + self.compact.bytes()).saturating_sub(before);
+    }
+}
+
+// the elements of sorted `slice` whose keys follow `after`, at most `limit` of them and any further
+// elements with the same key as the last, and whether any elements remain beyond these.
+fn window<'a, X, V: Ord, F: Fn(&X)->&V>(slice: &'a [X], key: F, after: Option<&V>, limit: usize) -> (&'a [X], bool) {
+    let start = after.map(|after| advance(slice, |x| key(x) <= after)).unwrap_or(0);
+    let slice = &slice[start ..];
+    if slice.len() <= limit {
+        (slice, false)
+    }
+    else {
+        let last = key(&slice[limit - 1]);
+        let end = limit + advance(&slice[limit ..], |x| key(x) == last);
+        (&slice[.. end], end < slice.len())
+    }
+}
+
+impl<Key: Ord+Hash+Clone, Val: Ord+Clone+'static, T: Ord+Clone> Default for Index<Key, Val, T> {
+    fn default() -> Self { Index::new() }
+}
+
+impl<Key: Ord+Hash+Clone, Val: Ord+Clone, T: Ord+Clone> IndexBackend<Key, Val, T> for Index<Key, Val, T> {
+    #[inline(always)]
+    fn count<P, F, Valid, W>(&mut self, data: &mut Vec<(P, u64, u64, W)>, func: &F, valid: &Valid, ident: u64)
+    where F: Fn(&P)->Key, Valid: Fn(&T)->bool {
+        Index::count(self, data, func, valid, ident)
+    }
+    #[inline(always)]
+    fn propose<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Option<Val>, Vec<Val>, W)>, func: &F, valid: &Valid, limit: usize)
+    where F: Fn(&P)->Key, Valid: Fn(&T)->bool {
+        Index::propose(self, data, func, valid, limit)
+    }
+    #[inline(always)]
+    fn intersect<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Vec<Val>, W)>, func: &F, valid: &Valid)
+    where F: Fn(&P)->Key, Valid: Fn(&T)->bool {
+        Index::intersect(self, data, func, valid)
+    }
+    #[inline(always)]
+    fn update(&mut self, time: T, updates: &mut Vec<((Key, Val), i32)>) -> Result<(), MemoryLimitExceeded> {
+        Index::update(self, time, updates)
+    }
+    #[inline(always)]
+    fn merge_to(&mut self, time: &T) { Index::merge_to(self, time) }
+    #[inline(always)]
+    fn initialize(&mut self, initial: &mut Vec<Vec<(Key, Val)>>) { Index::initialize(self, initial) }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn key(x: &(u32, char)) -> &u32 { &x.0 }
+
+    #[test]
+    fn window_takes_whole_keys() {
+        let slice = [(1, 'a'), (2, 'b'), (2, 'c'), (2, 'd'), (3, 'e'), (4, 'f')];
+
+        // the limit is exceeded to take every element with the same key as the last.
+        assert_eq!(window(&slice, key, None, 2), (&slice[.. 4], true));
+        assert_eq!(window(&slice, key, None, 4), (&slice[.. 4], true));
+        assert_eq!(window(&slice, key, None, 5), (&slice[.. 5], true));
+        assert_eq!(window(&slice, key, None, 6), (&slice[..], false));
+
+        // elements are taken only after `after`, which need not be present.
+        assert_eq!(window(&slice, key, Some(&1), 1), (&slice[1 .. 4], true));
+        assert_eq!(window(&slice, key, Some(&2), 1), (&slice[4 .. 5], true));
+        assert_eq!(window(&slice, key, Some(&3), 1), (&slice[5 ..], false));
+        assert_eq!(window(&slice, key, Some(&0), 10), (&slice[..], false));
+        assert_eq!(window(&slice, key, Some(&4), 1), (&slice[6 ..], false));
+        assert_eq!(window(&[], key, None, 1), (&[][..], false));
+    }
+
+    #[test]
+    fn propose_resumes_after_each_window() {
+        let mut index = Index::<u32, u32, u64>::new();
+        index.initialize(&mut vec![(0 .. 10).map(|v| (1, v)).collect(), vec![(2, 0)]]);
+        index.update(1, &mut vec![((1, 20), 1), ((1, 3), -1)]).unwrap();
+
+        let mut data = vec![((), None, Vec::new(), ())];
+        let mut proposed = Vec::new();
+        let mut rounds = 0;
+        loop {
+            index.propose(&mut data, &|_| 1, &|_| true, 3);
+            proposed.append(&mut data[0].2);
+            rounds += 1;
+            if data[0].1.is_none() { break; }
+        }
+        assert_eq!(proposed, vec![0, 1, 2, 4, 5, 6, 7, 8, 9, 20]);
+        assert!(rounds > 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn propose_requires_a_limit() {
+        let mut index = Index::<u32, u32, u64>::new();
+        index.propose(&mut [((), None, Vec::new(), ())], &|_| 1, &|_| true, 0);
     }
 }
@@ -24,18 +24,34 @@
 //! reports all changes to the occurrences of satisfying assignments to the values. The amount of work performed
 //! is no more than the worst-case optimal bound.
 
+// dataflow signatures are spelled out as streams of tuples, which read more plainly than aliases.
+#![allow(clippy::type_complexity)]
+
 extern crate timely;
+extern crate intersection;
+extern crate abomonation;
 
 use timely::dataflow::*;
 use timely::dataflow::operators::*;
 use timely::Data;
 
+use fanout::{Observer, Observe, StageObserver};
+
 mod index;
 mod extender;
+mod spill;
+mod guard;
+mod fanout;
 pub mod motif;
+pub mod partition;
+pub mod admission;
+pub mod agm;
 
-pub use index::Index;
-pub use extender::IndexStream;
+pub use index::{Index, IndexStats, MemoryLimitExceeded, BitmapValue, DenseKey};
+pub use extender::{IndexStream, Drain, Attributes};
+pub use spill::{Spill, SpillFailed};
+pub use guard::{Guard, GuardAction, GuardEvent};
+pub use fanout::{Fanout, Stage, StageCounts};
 
 /// Functionality used by GenericJoin to extend prefixes with new attributes.
 ///
@@ -46,49 +62,211 @@ pub trait StreamPrefixExtender<G: Scope, W: Data> {
     /// The type of the extentions.
     type Extension: Data;
     /// Updates each prefix with an upper bound on the number of extensions for this relation.
-    fn count(&self, Stream<G, (Self::Prefix, u64, u64, W)>, u64) -> Stream<G, (Self::Prefix, u64, u64, W)>;
+    fn count(&self, _: Stream<G, (Self::Prefix, u64, u64, W)>, _: u64) -> Stream<G, (Self::Prefix, u64, u64, W)>;
     /// Proposes each extension from this relation.
-    fn propose(&self, Stream<G, (Self::Prefix, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>;
+    fn propose(&self, _: Stream<G, (Self::Prefix, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>;
     /// Restricts proposals by those this relation would propose.
-    fn intersect(&self, Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>;
+    fn intersect(&self, _: Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>;
+    /// Indicates how this extender places prefixes across workers, if it is known.
+    ///
+    /// The output of each method is placed as indicated, and the `_local` methods may only be
+    /// applied to streams that are already placed this way.
+    fn route(&self) -> Option<Route> { None }
+    /// The `Drain` through which this extender's operators report their queues, if any.
+    ///
+    /// Code constructing several pipelines of extenders positions each through the `Drain`.
+    fn drain(&self) -> Option<Drain<G::Timestamp>> { None }
+    /// As `count`, for prefixes already placed as indicated by `route`.
+    fn count_local(&self, stream: Stream<G, (Self::Prefix, u64, u64, W)>, ident: u64) -> Stream<G, (Self::Prefix, u64, u64, W)> {
+        self.count(stream, ident)
+    }
+    /// As `propose`, for prefixes already placed as indicated by `route`.
+    fn propose_local(&self, stream: Stream<G, (Self::Prefix, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
+        self.propose(stream)
+    }
+    /// As `intersect`, for prefixes already placed as indicated by `route`.
+    fn intersect_local(&self, stream: Stream<G, (Self::Prefix, Vec<Self::Extension>, W)>) -> Stream<G, (Self::Prefix, Vec<Self::Extension>, W)> {
+        self.intersect(stream)
+    }
+}
+
+/// The placement of prefixes across workers by a `StreamPrefixExtender`.
+///
+/// Extenders with equal `Keyed` routes place each prefix at the same worker, and so a stream
+/// placed by one need not be exchanged before it is used by the other.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Route {
+    /// Prefixes are placed by a function of a key extracted from each prefix.
+    Keyed {
+        /// Identifies the function mapping keys to workers; see `IndexStream::partition`.
+        partition: usize,
+        /// The attribute of each prefix used as its key.
+        key: usize,
+    },
+    /// Prefixes are left in place, as the relation is available at every worker.
+    Anywhere,
+}
+
+// Indicates whether an extender with `route` can be applied to a stream placed as `current`
+// without exchanging it, and updates `current` to the placement of the extender's output.
+fn is_local(current: &mut Option<Route>, route: Option<Route>) -> bool {
+    match route {
+        Some(Route::Anywhere) => true,
+        route => {
+            let local = current.is_some() && *current == route;
+            *current = route;
+            local
+        }
+    }
+}
+
+/// Storage for a multiversion `(K, V)` relation, queried and maintained by an `IndexStream`.
+///
+/// `Index` is the default, in-memory implementation. Other stores, for example disk-backed 
+/// ones, can be used by constructing an `IndexStream` with `IndexStream::from_backend`. In each
+/// query method, `func` extracts a key from each record, and `valid` indicates which update 
+/// times should be reflected in the answer.
+pub trait IndexBackend<K, V, T> {
+    /// Overwrites each record's count and `ident` if this relation would propose fewer extensions.
+    fn count<P, F, Valid, W>(&mut self, data: &mut Vec<(P, u64, u64, W)>, func: &F, valid: &Valid, ident: u64)
+    where F: Fn(&P)->K, Valid: Fn(&T)->bool;
+    /// Adds to each record the next extensions this relation proposes for its key.
+    ///
+    /// Extensions are proposed in increasing order, and each record's second field indicates the
+    /// extension after which to resume, or `None` to start from the first. About `limit` values 
+    /// are added to each record, after which the resume point is set to the last value considered
+    /// if more may remain, and to `None` otherwise. The `limit` is at least one.
+    fn propose<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Option<V>, Vec<V>, W)>, func: &F, valid: &Valid, limit: usize)
+    where F: Fn(&P)->K, Valid: Fn(&T)->bool;
+    /// Restricts the extensions of each record to those this relation would propose.
+    fn intersect<P, F, Valid, W>(&mut self, data: &mut Vec<(P, Vec<V>, W)>, func: &F, valid: &Valid)
+    where F: Fn(&P)->K, Valid: Fn(&T)->bool;
+    /// Introduces updates at `time`, to be reflected in queries at or after `time`.
+    ///
+    /// A backend that cannot hold the updates returns an error, and leaves them in `updates`.
+    fn update(&mut self, time: T, updates: &mut Vec<((K, V), i32)>) -> Result<(), MemoryLimitExceeded>;
+    /// Indicates that updates at times less or equal to `time` need no longer be distinguished.
+    fn merge_to(&mut self, time: &T);
+    /// Sets an initial collection of `(K, V)` pairs, supplied in sorted order.
+    fn initialize(&mut self, initial: &mut Vec<Vec<(K, V)>>);
 }
 
 /// Extension method for generic join functionality.
 pub trait GenericJoin<G:Scope, P:Data, W: Data> {
     /// Extends a stream of prefixes using the supplied prefix extenders.
-    fn extend<'a, E: Data>(&self, extenders: Vec<Box<StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>)
+    fn extend<'a, E: Data>(&self, extenders: Vec<Box<dyn StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>)
         -> Stream<G, (P, Vec<E>, W)>;
+    /// Extends a stream of prefixes as `extend` does, and also produces the work of each timestamp 
+    /// as the counts of `stage`.
+    fn extend_counted<'a, E: Data>(&self, extenders: Vec<Box<dyn StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>, stage: Stage)
+        -> (Stream<G, (P, Vec<E>, W)>, Stream<G, (Stage, StageCounts)>) where G::Timestamp: ::std::hash::Hash;
 }
 
 // A layer of GenericJoin, in which a collection of prefixes are extended by one attribute
 impl<G: Scope, P:Data, W: Data> GenericJoin<G, P, W> for Stream<G, (P, W)> {
-    fn extend<'a, E>(&self, extenders: Vec<Box<StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>) -> Stream<G, (P, Vec<E>, W)> 
+    fn extend<'a, E>(&self, extenders: Vec<Box<dyn StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>) -> Stream<G, (P, Vec<E>, W)> 
     where E: Data {
+        extend_observed(self, extenders, None, &())
+    }
+    fn extend_counted<'a, E>(&self, extenders: Vec<Box<dyn StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>, stage: Stage) -> (Stream<G, (P, Vec<E>, W)>, Stream<G, (Stage, StageCounts)>) 
+    where E: Data, G::Timestamp: ::std::hash::Hash {
+        let observer = StageObserver::new(stage);
+        let extensions = extend_observed(self, extenders, None, &observer);
+        (extensions, observer.counts(&self.scope()))
+    }
+}
+
+// extends `prefixes` using `extenders`, with `observer` counting the records at each point.
+//
+// If `proposer` is set, the indicated extender proposes extensions for all prefixes, rather than
+// the extender with the fewest for each prefix, and the counts are skipped.
+fn extend_observed<'a, G: Scope, P: Data, W: Data, E: Data, O: Observer<G>>(prefixes: &Stream<G, (P, W)>, extenders: Vec<Box<dyn StreamPrefixExtender<G, W, Prefix=P, Extension=E>+'a>>, proposer: Option<usize>, observer: &O) -> Stream<G, (P, Vec<E>, W)> {
 
-        if extenders.len() == 1 {
-            extenders[0].propose(self.clone())
+    let prefixes = observer.observe(prefixes, Observe::Prefixes, |_| 1);
+    let extensions = if extenders.len() == 1 {
+        let proposals = if is_local(&mut None, extenders[0].route()) {
+            extenders[0].propose_local(prefixes)
+        }
+        else {
+            extenders[0].propose(prefixes)
+        };
+        observer.observe(&proposals, Observe::Proposed(0), |x: &(P, Vec<E>, W)| x.1.len() as u64)
+    }
+    else if let Some(index) = proposer {
+        let mut current = None;
+        let mut extensions = if is_local(&mut current, extenders[index].route()) {
+            extenders[index].propose_local(prefixes)
         }
         else {
-            let mut counts = self.map(|(p,s)| (p, 1 << 31, 0, s));
-            for (index,extender) in extenders.iter().enumerate() {
-                counts = extender.count(counts, index as u64);
+            extenders[index].propose(prefixes)
+        };
+        extensions = observer.observe(&extensions, Observe::Proposed(index), |x: &(P, Vec<E>, W)| x.1.len() as u64);
+        for other in (0..extenders.len()).filter(|&x| x != index) {
+            extensions = if is_local(&mut current, extenders[other].route()) {
+                extenders[other].intersect_local(extensions)
+            }
+            else {
+                extenders[other].intersect(extensions)
+            };
+            extensions = observer.observe(&extensions, Observe::Intersected(other), |x: &(P, Vec<E>, W)| x.1.len() as u64);
+        }
+        extensions
+    }
+    else {
+        // the placement of the stream, if known; each stage need not exchange its input
+        // if it would place prefixes exactly as the stage before it did, or if its 
+        // relation is available at every worker.
+        let mut counts = prefixes.map(|(p,s)| (p, 1 << 31, 0, s));
+        let mut route = None;
+        for (index,extender) in extenders.iter().enumerate() {
+            counts = if is_local(&mut route, extender.route()) {
+                extender.count_local(counts, index as u64)
             }
+            else {
+                extender.count(counts, index as u64)
+            };
+        }
 
-            let parts = counts.partition(extenders.len() as u64, |(p, _, i, w)| (i, (p, w)));
+        // partitioning is local, and so each part remains placed as `route` indicates.
+        let parts = counts.partition(extenders.len() as u64, |(p, _, i, w)| (i, (p, w)));
 
-            let mut results = Vec::new();
-            for (index, nominations) in parts.into_iter().enumerate() {
-                let mut extensions = extenders[index].propose(nominations);
-                for other in (0..extenders.len()).filter(|&x| x != index) {
-                    extensions = extenders[other].intersect(extensions);
-                }
+        // each part is a sibling of the others, downstream of the counts but not of each other.
+        let drain = extenders.iter().filter_map(|x| x.drain()).next();
+        let counted = drain.as_ref().map(|x| x.position());
+        let mut ends = Vec::new();
 
-                results.push(extensions);    // save extensions
+        let mut results = Vec::new();
+        for (index, nominations) in parts.into_iter().enumerate() {
+            if let (Some(drain), Some(counted)) = (drain.as_ref(), counted.as_ref()) {
+                drain.set_position(counted.clone());
+            }
+            let mut current = route;
+            let mut extensions = if is_local(&mut current, extenders[index].route()) {
+                extenders[index].propose_local(nominations)
+            }
+            else {
+                extenders[index].propose(nominations)
+            };
+            extensions = observer.observe(&extensions, Observe::Proposed(index), |x: &(P, Vec<E>, W)| x.1.len() as u64);
+            for other in (0..extenders.len()).filter(|&x| x != index) {
+                extensions = if is_local(&mut current, extenders[other].route()) {
+                    extenders[other].intersect_local(extensions)
+                }
+                else {
+                    extenders[other].intersect(extensions)
+                };
+                extensions = observer.observe(&extensions, Observe::Intersected(other), |x: &(P, Vec<E>, W)| x.1.len() as u64);
             }
 
-            self.scope().concatenate(results).map(|(p,es,w)| (p,es,w))
+            results.push(extensions);    // save extensions
+            if let Some(drain) = drain.as_ref() { ends.extend(drain.position()); }
         }
-    }
+        if let Some(drain) = drain.as_ref() { drain.set_position(ends); }
+
+        prefixes.scope().concatenate(results).map(|(p,es,w)| (p,es,w))
+    };
+
+    observer.observe(&extensions, Observe::Extensions, |x: &(P, Vec<E>, W)| x.1.len() as u64)
 }
 
 /// Reports the number of elements satisfing the predicate.
@@ -108,16 +286,16 @@ pub fn advance<T, F: Fn(&T)->bool>(slice: &[T], function: F) -> usize {
         let mut step = 1;
         while index + step < slice.len() && function(&slice[index + step]) {
             index += step;
-            step = step << 1;
+            step <<= 1;
         }
 
         // advance in exponentially shrinking steps.
-        step = step >> 1;
+        step >>= 1;
         while step > 0 {
             if index + step < slice.len() && function(&slice[index + step]) {
                 index += step;
             }
-            step = step >> 1;
+            step >>= 1;
         }
 
         index += 1;
@@ -9,38 +9,340 @@
 //! exist is an instance of the triangle motif.
 
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 
-use timely::ExchangeData;
+use timely::{Data, ExchangeData};
 use timely::dataflow::*;
 use timely::dataflow::operators::*;
+use timely::dataflow::channels::pact::{Exchange, Pipeline};
+use timely::progress::Timestamp;
+use timely::order::PartialOrder;
 
-use index::Index;
-use ::{IndexStream, StreamPrefixExtender, GenericJoin};
+use index::{Index, IndexStats, MemoryLimitExceeded};
+use ::{IndexStream, Attributes, StreamPrefixExtender, Drain, Spill, Fanout, Stage, StageCounts};
+use fanout::StageObserver;
+use guard::{Guard, QueryGuard};
 
 pub type Node = u32;
 pub type Edge = (Node, Node);
 
+// the default bound on the instances of a component each worker holds for a cross product.
+const DEFAULT_CROSS_LIMIT: usize = 1 << 20;
+
+/// A motif description, checked so that dataflows can be planned for it.
+///
+/// Each edge `(src, dst)` relates two attributes, numbered from zero. A valid motif has at least 
+/// one edge and uses every attribute from zero to its largest. No edge may relate an attribute to 
+/// itself, and no two edges may relate the same pair of attributes, in either direction, as the 
+/// planner binds each new attribute only through edges to attributes bound before it.
+///
+/// A motif may be disconnected, in which case each component is tracked separately and their
+/// instances are combined by cross products, as `warnings` reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Motif {
+    edges: Vec<(usize, usize)>,
+    attributes: usize,
+}
+
+impl Motif {
+    /// Checks the motif with edges `edges`.
+    pub fn new(edges: &[(usize, usize)]) -> Result<Self, MotifError> {
+        if edges.is_empty() { return Err(MotifError::Empty); }
+        let attributes = edges.iter().map(|&(x,y)| ::std::cmp::max(x, y) + 1).max().unwrap_or(0);
+
+        for (index, &(src, dst)) in edges.iter().enumerate() {
+            if src == dst { return Err(MotifError::SelfLoop(src, dst)); }
+            for &(other_src, other_dst) in edges[.. index].iter() {
+                if (other_src, other_dst) == (src, dst) { return Err(MotifError::Duplicate(src, dst)); }
+                if (other_src, other_dst) == (dst, src) { return Err(MotifError::Reciprocal(src, dst)); }
+            }
+        }
+
+        let mut used = vec![false; attributes];
+        for &(src, dst) in edges.iter() { used[src] = true; used[dst] = true; }
+        if let Some(attribute) = used.iter().position(|&x| !x) {
+            return Err(MotifError::Unused(attribute));
+        }
+
+        Ok(Motif { edges: edges.to_vec(), attributes })
+    }
+
+    /// The relations of each connected component, in order of their first relation.
+    pub fn components(&self) -> Vec<Vec<usize>> {
+        let mut components: Vec<Vec<usize>> = Vec::new();
+        for relation in 0 .. self.edges.len() {
+            if !components.iter().any(|c| c.contains(&relation)) {
+                let attrs = order_attributes(relation, &self.edges).0;
+                components.push((0 .. self.edges.len()).filter(|&r| attrs.contains(&self.edges[r].0)).collect());
+            }
+        }
+        components
+    }
+
+    /// Reports aspects of the motif likely to make tracking it expensive.
+    pub fn warnings(&self) -> Vec<MotifWarning> {
+        let components = self.components();
+        let attrs = |relations: &Vec<usize>| {
+            let mut attrs = relations.iter().flat_map(|&r| vec![self.edges[r].0, self.edges[r].1]).collect::<Vec<_>>();
+            attrs.sort();
+            attrs.dedup();
+            attrs
+        };
+        let mut warnings = Vec::new();
+        let mut left = components.first().map(&attrs).unwrap_or_default();
+        for component in components.iter().skip(1) {
+            let right = attrs(component);
+            warnings.push(MotifWarning::CrossProduct { left: left.clone(), right: right.clone() });
+            left.extend(right);
+            left.sort();
+        }
+        warnings
+    }
+
+    /// The edges of the motif, each of which is a relation of its dataflows.
+    pub fn edges(&self) -> &[(usize, usize)] { &self.edges }
+
+    /// The number of attributes of the motif.
+    pub fn attributes(&self) -> usize { self.attributes }
+}
+
+/// The reason a motif description is invalid, or unsuitable for a query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MotifError {
+    /// The motif has no edges.
+    Empty,
+    /// An edge relates an attribute to itself.
+    SelfLoop(usize, usize),
+    /// An edge appears more than once.
+    Duplicate(usize, usize),
+    /// An edge appears along with its reverse.
+    Reciprocal(usize, usize),
+    /// An attribute below the largest is in no edge.
+    Unused(usize),
+    /// The motif has the indicated number of components, but the query requires it to be connected.
+    Disconnected(usize),
+}
+
+impl fmt::Display for MotifError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MotifError::Empty => write!(f, "motif has no edges"),
+            MotifError::SelfLoop(src, dst) => write!(f, "edge ({}, {}) relates an attribute to itself", src, dst),
+            MotifError::Duplicate(src, dst) => write!(f, "edge ({}, {}) appears more than once", src, dst),
+            MotifError::Reciprocal(src, dst) => write!(f, "edge ({}, {}) appears along with its reverse", src, dst),
+            MotifError::Unused(attribute) => write!(f, "attribute {} is in no edge, but a larger attribute is", attribute),
+            MotifError::Disconnected(components) => write!(f, "motif has {} components, but the query requires one", components),
+        }
+    }
+}
+
+impl Error for MotifError { }
+
+/// A report of a cross product that would hold more instances of a motif component than its limit.
+///
+/// The cross product then stops at every worker, and its query reports no changes at the time it
+/// would have exceeded the limit, nor at later times.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CrossLimitExceeded {
+    /// The number of instances the worker that stopped would have held.
+    pub held: usize,
+    /// The limit on the instances held.
+    pub limit: usize,
+}
+
+impl fmt::Display for CrossLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cross product holds {} instances of a motif component, exceeding its limit of {}", self.held, self.limit)
+    }
+}
+
+impl Error for CrossLimitExceeded { }
+
+/// An aspect of a motif likely to make tracking it expensive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MotifWarning {
+    /// No relation connects the attributes `left` to the attributes `right`, and so each instance
+    /// on `left` is paired with each instance on `right`. The work and the state held grow with
+    /// the product of their numbers of instances.
+    CrossProduct { left: Vec<usize>, right: Vec<usize> },
+}
+
+impl fmt::Display for MotifWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MotifWarning::CrossProduct { ref left, ref right } => 
+                write!(f, "no relation connects attributes {:?} and {:?}; their instances are combined by a cross product", left, right),
+        }
+    }
+}
+
 /// Handles to the forward and reverse graph indices.
 pub struct GraphStreamIndexHandle<T> {
     forward: Rc<RefCell<Index<Node, Node, T>>>,
     reverse: Rc<RefCell<Index<Node, Node, T>>>,
+    exceeded: (Rc<Cell<Option<MemoryLimitExceeded>>>, Rc<Cell<Option<MemoryLimitExceeded>>>),
+    crossed: Rc<Cell<Option<CrossLimitExceeded>>>,
+    holds: Holds<T>,
+    merges: RefCell<Vec<T>>,
 }
 
+// for each attached dataflow, whether its queries may still read the indices at a time.
+type Holds<T> = Rc<RefCell<Vec<Box<dyn Fn(&T)->bool>>>>;
+
 impl<T: Ord+Clone+::std::fmt::Debug> GraphStreamIndexHandle<T> {
     /// Merges both handles up to the specified time, compacting their representations.
+    ///
+    /// Queries attached through `SharedGraphIndex::attach` read the indices as of the times they
+    /// have not yet passed, and so merges to those times are held back, and made by the first call
+    /// to `merge_to` once the queries have passed them or have been cancelled.
     pub fn merge_to(&self, time: &T) {
-        self.forward.borrow_mut().merge_to(time);
-        self.reverse.borrow_mut().merge_to(time);
+        let mut merges = self.merges.borrow_mut();
+        merges.push(time.clone());
+        let holds = self.holds.borrow();
+        let (ready, held): (Vec<T>, Vec<T>) = merges.drain(..).partition(|time| !holds.iter().any(|hold| hold(time)));
+        *merges = held;
+        for time in ready.iter() {
+            self.forward.borrow_mut().merge_to(time);
+            self.reverse.borrow_mut().merge_to(time);
+        }
+    }
+
+    /// Reports the sizes of the forward and reverse indices, respectively.
+    pub fn stats(&self) -> (IndexStats, IndexStats) {
+        (self.forward.borrow().stats(), self.reverse.borrow().stats())
+    }
+
+    /// Sets or clears a bound on the approximate bytes used by each of the indices.
+    ///
+    /// An index that receives updates exceeding its limit refuses them, rather than grow without 
+    /// bound, and reports this through `exceeded`.
+    pub fn set_memory_limit(&self, limit: Option<usize>) {
+        self.forward.borrow_mut().set_memory_limit(limit);
+        self.reverse.borrow_mut().set_memory_limit(limit);
+    }
+
+    /// Reports the first updates refused by either index for exceeding its memory limit.
+    ///
+    /// Once updates are refused the indices no longer reflect the graph, and the results of 
+    /// queries against them are incomplete; the computation should be stopped.
+    pub fn exceeded(&self) -> Option<MemoryLimitExceeded> {
+        self.exceeded.0.get().or(self.exceeded.1.get())
+    }
+
+    /// Reports the first cross product of any query to exceed its limit; see `set_cross_limit`.
+    ///
+    /// The query of that cross product reports no further changes, and its results are incomplete.
+    pub fn cross_exceeded(&self) -> Option<CrossLimitExceeded> {
+        self.crossed.get()
+    }
+}
+
+/// Stops the motif queries of a dataflow attached to shared indices.
+///
+/// Once cancelled, the queries discard their updates, the prefixes of each of their stages, and 
+/// the prefixes their extenders hold, and drop the capabilities of all of these, rather than 
+/// wait for the indices to reach their times. The queries report no further results, and no longer
+/// hold back merges of the indices. Once the dataflow's inputs are also closed, no operator holds
+/// a capability, and the dataflow is retired without further work.
+#[derive(Clone, Default)]
+pub struct Cancel {
+    cancelled: Rc<Cell<bool>>,
+}
+
+impl Cancel {
+    /// Allocates a new `Cancel`, not yet cancelled.
+    pub fn new() -> Self { Cancel::default() }
+    /// Cancels the queries.
+    pub fn cancel(&self) { self.cancelled.set(true); }
+    /// Indicates whether the queries have been cancelled.
+    pub fn is_cancelled(&self) -> bool { self.cancelled.get() }
+
+    // passes `stream` through until cancelled, and discards it thereafter.
+    fn gate<G: Scope, D: Data>(&self, stream: &Stream<G, D>) -> Stream<G, D> {
+        let cancelled = self.cancelled.clone();
+        let mut buffer = Vec::new();
+        stream.unary(Pipeline, "Cancel", move |_,_| move |input, output| {
+            input.for_each(|time, data| {
+                data.swap(&mut buffer);
+                if cancelled.get() { buffer.clear(); }
+                else { output.session(&time).give_vec(&mut buffer); }
+            });
+        })
+    }
+}
+
+/// The indices of a `GraphStreamIndex`, apart from the dataflow maintaining them.
+///
+/// Motif queries can be attached to the indices from other dataflows of the same worker, including
+/// those constructed while updates flow into the indices. Each worker must construct the same 
+/// dataflows in the same order, as usual.
+pub struct SharedGraphIndex<T: Timestamp, H1: Fn(Node)->u64, H2: Fn(Node)->u64> {
+    forward: IndexStream<Node, Node, H1, T>,
+    reverse: IndexStream<Node, Node, H2, T>,
+    guard: Option<Guard<T>>,
+    fanout: Option<Fanout>,
+    cross_limit: usize,
+    crossed: Rc<Cell<Option<CrossLimitExceeded>>>,
+    holds: Holds<T>,
+}
+
+impl<T: Timestamp+Ord+::std::hash::Hash, H1: Fn(Node)->u64+'static, H2: Fn(Node)->u64+'static> SharedGraphIndex<T, H1, H2> {
+
+    /// Attaches the indices to the dataflow of `updates`, in which motif queries may then be constructed.
+    ///
+    /// The queries report the changes to their motifs' instances at the times of `updates`, which 
+    /// should be the updates the indices receive from some time on; to start reporting changes from
+    /// time `t`, supply the updates from `t` on. The queries read the indices as they were before 
+    /// each update, and so `GraphStreamIndexHandle::merge_to` holds back merges of the indices to 
+    /// times the queries have not yet passed.
+    /// 
+    /// The queries stop once the returned `Cancel` is cancelled.
+    pub fn attach<G: Scope<Timestamp=T>>(&self, updates: &Stream<G, (Edge, i32)>) -> (GraphStreamIndex<G, H1, H2>, Cancel) {
+        let cancel = Cancel::new();
+        let (mut forward, mut reverse) = (self.forward.clone(), self.reverse.clone());
+        forward.set_cancelled(Some(cancel.cancelled.clone()));
+        reverse.set_cancelled(Some(cancel.cancelled.clone()));
+        let hold = ProbeHandle::new();
+        let (probe, cancelled) = (hold.clone(), cancel.clone());
+        self.holds.borrow_mut().push(Box::new(move |time| !cancelled.is_cancelled() && probe.less_equal(time)));
+        let index = GraphStreamIndex {
+            updates: cancel.gate(updates),
+            forward,
+            reverse,
+            guard: self.guard.clone(),
+            fanout: self.fanout.clone(),
+            cross_limit: self.cross_limit,
+            crossed: self.crossed.clone(),
+            counts: RefCell::new(Vec::new()),
+            cancel: Some(cancel.clone()),
+            hold: Some(hold),
+            holds: self.holds.clone(),
+        };
+        (index, cancel)
     }
 }
 
 /// Indices and updates for a graph stream.
+///
+/// Both indices record the neighbors of high-degree nodes in bitmaps, as any `Index` of `u32`
+/// values does, and intersect neighbor lists with vectorized kernels; see `Index::with_simd`.
 pub struct GraphStreamIndex<G: Scope, H1: Fn(Node)->u64, H2: Fn(Node)->u64> 
     where G::Timestamp: Ord+::std::hash::Hash {
     updates: Stream<G, (Edge, i32)>,
     pub forward: IndexStream<Node, Node, H1, G::Timestamp>,
     pub reverse: IndexStream<Node, Node, H2, G::Timestamp>,
+    guard: Option<Guard<G::Timestamp>>,
+    fanout: Option<Fanout>,
+    counts: RefCell<Vec<Stream<G, (Stage, StageCounts)>>>,
+    cross_limit: usize,
+    crossed: Rc<Cell<Option<CrossLimitExceeded>>>,
+    cancel: Option<Cancel>,
+    // a probe of the queries of an attached dataflow, which holds back merges of the indices.
+    hold: Option<ProbeHandle<G::Timestamp>>,
+    holds: Holds<G::Timestamp>,
 }
 
 impl<G: Scope, H1: Fn(Node)->u64+'static, H2: Fn(Node)->u64+'static> GraphStreamIndex<G, H1, H2> where G::Timestamp: Ord+::std::hash::Hash {
@@ -49,112 +351,508 @@ impl<G: Scope, H1: Fn(Node)->u64+'static, H2: Fn(Node)->u64+'static> GraphStream
     pub fn from(initially: Stream<G, Edge>, 
                 updates: Stream<G, (Edge, i32)>, hash1: H1, hash2: H2) -> (Self, GraphStreamIndexHandle<G::Timestamp>) {
 
-        let forward = IndexStream::from(hash1, &initially, &updates);
-        let reverse = IndexStream::from(hash2, &initially.map(|(src,dst)| (dst,src)),
+        let forward = IndexStream::from_backend(Index::new().with_simd(), hash1, &initially, &updates);
+        let reverse = IndexStream::from_backend(Index::new().with_simd(), hash2, &initially.map(|(src,dst)| (dst,src)),
                                                &updates.map(|((src,dst),wgt)| ((dst,src),wgt)));
-        let index = GraphStreamIndex {
-            updates: updates,
-            forward: forward,
-            reverse: reverse,
-        };
-        let handles = GraphStreamIndexHandle {
-            forward: index.forward.index.clone(),
-            reverse: index.reverse.index.clone(),
-        };
-        (index, handles)
+        Self::assemble(updates, forward, reverse)
+    }
+
+    /// Constructs a new graph stream index with dense node storage from initial edges and an update stream.
+    ///
+    /// The hash functions must route each node to worker `node % peers`, as `|k| k as u64` does.
+    pub fn from_dense(initially: Stream<G, Edge>, 
+                updates: Stream<G, (Edge, i32)>, hash1: H1, hash2: H2) -> (Self, GraphStreamIndexHandle<G::Timestamp>) {
+
+        let (index, peers) = (initially.scope().index(), initially.scope().peers());
+        let forward = IndexStream::from_backend(Index::dense(index, peers).with_simd(), hash1, &initially, &updates);
+        let reverse = IndexStream::from_backend(Index::dense(index, peers).with_simd(), hash2, &initially.map(|(src,dst)| (dst,src)),
+                                                     &updates.map(|((src,dst),wgt)| ((dst,src),wgt)));
+        Self::assemble(updates, forward, reverse)
     }
 
     /// Constructs a new graph stream index from initial edges and an update stream.
     pub fn from_separately(initially_f: Stream<G, Edge>, initially_r: Stream<G, Edge>, 
                 updates: Stream<G, (Edge, i32)>, hash1: H1, hash2: H2) -> (Self, GraphStreamIndexHandle<G::Timestamp>) {
 
-        let forward = IndexStream::from(hash1, &initially_f, &updates);
-        let reverse = IndexStream::from(hash2, &initially_r.map(|(src,dst)| (dst,src)),
+        let forward = IndexStream::from_backend(Index::new().with_simd(), hash1, &initially_f, &updates);
+        let reverse = IndexStream::from_backend(Index::new().with_simd(), hash2, &initially_r.map(|(src,dst)| (dst,src)),
                                                &updates.map(|((src,dst),wgt)| ((dst,src),wgt)));
-        let index = GraphStreamIndex {
-            updates: updates,
-            forward: forward,
-            reverse: reverse,
-        };
-        let handles = GraphStreamIndexHandle {
-            forward: index.forward.index.clone(),
-            reverse: index.reverse.index.clone(),
-        };
-        (index, handles)
+        Self::assemble(updates, forward, reverse)
     }
 
-        /// Constructs a new graph stream index from initial edges and an update stream.
+    /// Constructs a new graph stream index from initial edges and an update stream.
     pub fn from_separately_static(initially_f: Stream<G, Edge>, initially_r: Stream<G, Edge>, 
                 queries: Stream<G, (Edge, i32)>, hash1: H1, hash2: H2) -> (Self, GraphStreamIndexHandle<G::Timestamp>) {
 
-        let forward = IndexStream::from(hash1, &initially_f, &Vec::new().to_stream(&mut initially_f.scope()));
-        let reverse = IndexStream::from(hash2, &initially_r.map(|(src,dst)| (dst,src)),
+        let forward = IndexStream::from_backend(Index::new().with_simd(), hash1, &initially_f, &Vec::new().to_stream(&mut initially_f.scope()));
+        let reverse = IndexStream::from_backend(Index::new().with_simd(), hash2, &initially_r.map(|(src,dst)| (dst,src)),
                                                &Vec::new().to_stream(&mut initially_r.scope()));
-        let index = GraphStreamIndex {
-            updates: queries,
-            forward: forward,
-            reverse: reverse,
-        };
+        Self::assemble(queries, forward, reverse)
+    }
+
+    // wraps `forward` and `reverse` indices maintained from `updates` with default settings.
+    fn assemble(updates: Stream<G, (Edge, i32)>,
+                forward: IndexStream<Node, Node, H1, G::Timestamp>,
+                reverse: IndexStream<Node, Node, H2, G::Timestamp>) -> (Self, GraphStreamIndexHandle<G::Timestamp>) {
+
         let handles = GraphStreamIndexHandle {
-            forward: index.forward.index.clone(),
-            reverse: index.reverse.index.clone(),
+            forward: forward.index.clone(),
+            reverse: reverse.index.clone(),
+            exceeded: (forward.exceeded.clone(), reverse.exceeded.clone()),
+            crossed: Rc::new(Cell::new(None)),
+            holds: Rc::new(RefCell::new(Vec::new())),
+            merges: RefCell::new(Vec::new()),
+        };
+        let index = GraphStreamIndex {
+            updates,
+            forward,
+            reverse,
+            guard: None,
+            fanout: None,
+            cross_limit: DEFAULT_CROSS_LIMIT,
+            crossed: handles.crossed.clone(),
+            counts: RefCell::new(Vec::new()),
+            cancel: None,
+            hold: None,
+            holds: handles.holds.clone(),
         };
         (index, handles)
     }
 
-    /// Constructs a dataflow subgraph to track a described motif.
-    pub fn track_motif<'a>(&self, description: &[(usize, usize)]) -> Stream<G, (Vec<Node>, i32)> where G: 'a {
-        let mut result = self.updates.filter(|_| false).map(|_| (Vec::new(), 0));
-        for relation in 0 .. description.len() {
-            result = result.concat(&self.relation_update(relation, &description));
+    /// Bounds the number of extensions in each proposal, for dataflows subsequently constructed.
+    pub fn set_chunk(&mut self, chunk: usize) {
+        self.forward.set_chunk(chunk);
+        self.reverse.set_chunk(chunk);
+    }
+
+    /// Enables or disables downstream-first execution, for dataflows subsequently constructed.
+    ///
+    /// When enabled, the extenders of both indices share a `Drain`, and each relation's dataflow
+    /// drains its later stages before proposing further extensions in earlier stages.
+    pub fn set_draining(&mut self, draining: bool) {
+        let drain = if draining { Some(Drain::new()) } else { None };
+        self.forward.set_drain(drain.clone());
+        self.reverse.set_drain(drain);
+    }
+
+    /// Spills blocked prefixes to disk as `spill` indicates, for dataflows subsequently constructed.
+    pub fn set_spill(&mut self, spill: Option<Spill>) {
+        self.forward.set_spill(spill.clone());
+        self.reverse.set_spill(spill);
+    }
+
+    /// Limits the work of each subsequently constructed query as `guard` indicates, or ceases to if `None`.
+    ///
+    /// Each call to `track_motif` or `build_motif` is a separate query, whose results are held
+    /// for each timestamp until it is complete, and suppressed at every worker if any exceeds the limits.
+    pub fn set_guard(&mut self, guard: Option<Guard<G::Timestamp>>) {
+        self.guard = guard;
+    }
+
+    /// Counts the work of each stage of subsequently constructed queries, numbered by `fanout`, or
+    /// ceases to if `None`.
+    ///
+    /// Each relation's dataflow reports its extension stages in order, numbered from zero. The 
+    /// counts are available from `stage_counts`.
+    pub fn set_fanout(&mut self, fanout: Option<Fanout>) {
+        self.fanout = fanout;
+    }
+
+    /// The counts of each counted stage of the queries constructed so far, at each timestamp.
+    ///
+    /// Each stage reports its counts at this worker for a timestamp once its work there is done.
+    pub fn stage_counts(&self) -> Stream<G, (Stage, StageCounts)> {
+        self.updates.scope().concatenate(self.counts.borrow().clone())
+    }
+
+    /// Bounds the instances of a component each worker holds for the cross products of disconnected
+    /// motifs subsequently constructed, on either side of each cross product.
+    ///
+    /// A cross product that would exceed the bound at any worker stops at every worker before it 
+    /// produces changes at that time, rather than grow without bound, and reports this through 
+    /// `GraphStreamIndexHandle::cross_exceeded`.
+    pub fn set_cross_limit(&mut self, limit: usize) {
+        self.cross_limit = limit;
+    }
+
+    /// The indices and current settings, for attaching queries from other dataflows.
+    pub fn share(&self) -> SharedGraphIndex<G::Timestamp, H1, H2> {
+        SharedGraphIndex {
+            forward: self.forward.clone(),
+            reverse: self.reverse.clone(),
+            guard: self.guard.clone(),
+            fanout: self.fanout.clone(),
+            cross_limit: self.cross_limit,
+            crossed: self.crossed.clone(),
+            holds: self.holds.clone(),
         }
-        result
     }
+
+    /// Constructs a dataflow subgraph to track a described motif.
+    pub fn track_motif<'a>(&self, motif: &Motif) -> Stream<G, (Vec<Node>, i32)> where G: 'a {
+        self.track_motif_with(motif, &PlanOverrides::new()).expect("valid motifs plan without overrides")
+    }
+
+    /// Constructs a dataflow subgraph to track a described motif, with plans changed by `overrides`.
+    ///
+    /// No dataflow is constructed if any override is invalid.
+    pub fn track_motif_with<'a>(&self, motif: &Motif, overrides: &PlanOverrides) -> Result<Stream<G, (Vec<Node>, i32)>, PlanError> where G: 'a {
+        let description = motif.edges();
+        let plans = (0 .. description.len()).map(|relation| plan_relation(relation, description, overrides)).collect::<Result<Vec<_>, _>>()?;
+        let guard = self.guard.as_ref().map(|x| x.query().with_drain(self.forward.drain()));
+        let query = self.fanout.as_ref().map(|x| x.query()).unwrap_or(0);
+        let mut trips = Vec::new();
+        let result = self.motif_update(query, motif, plans, &guard, &mut trips, &mut HashMap::new());
+        Ok(self.held(match guard {
+            Some(guard) => guard.results(&result, &trips),
+            None => result,
+        }))
+    }
+
+    /// Constructs dataflow subgraphs to track several motifs, sharing the work they have in common.
+    ///
+    /// Each relation's dataflow binds the attributes of its updates and then extends them by a 
+    /// sequence of stages. The dataflows of all relations of all motifs form a trie, in which two 
+    /// dataflows whose first stages extend the same prefixes by the same constraints share those 
+    /// stages, and their extensions are fanned out to each dataflow's later stages. For example,
+    /// motifs extending a common triangle in different ways share the stages binding the triangle.
+    ///
+    /// The motifs are a single query for the purposes of `set_guard` and `set_fanout`, and a shared 
+    /// stage is counted as a stage of the first relation to use it. The results are one stream for
+    /// each motif, in order.
+    pub fn track_motifs<'a>(&self, motifs: &[Motif]) -> Vec<Stream<G, (Vec<Node>, i32)>> where G: 'a {
+        let guard = self.guard.as_ref().map(|x| x.query().with_drain(self.forward.drain()));
+        let query = self.fanout.as_ref().map(|x| x.query()).unwrap_or(0);
+        let mut trie = HashMap::new();
+        let mut trips = Vec::new();
+        let mut ends = Vec::new();
+        let results = motifs.iter().map(|motif| {
+            let description = motif.edges();
+            let plans = (0 .. description.len()).map(|relation| plan_relation(relation, description, &PlanOverrides::new()).expect("valid motifs plan without overrides")).collect();
+            let result = self.motif_update(query, motif, plans, &guard, &mut trips, &mut trie);
+            ends.extend(self.position());
+            result
+        }).collect::<Vec<_>>();
+        self.set_position(ends);
+        let results = match guard {
+            Some(guard) => {
+                // the results of all motifs are held together, and then separated again.
+                let tagged = results.iter().enumerate().map(|(index, stream)| stream.map(move |(p, w)| ((index, p), w))).collect::<Vec<_>>();
+                let held = guard.results(&self.updates.scope().concatenate(tagged), &trips);
+                held.partition(motifs.len() as u64, |((index, p), w)| (index as u64, (p, w)))
+            },
+            None => results,
+        };
+        results.into_iter().map(|result| self.held(result)).collect()
+    }
+
     /// Constructs a dataflow subgraph to track a described motif.
-    pub fn build_motif<'a>(&self, description: &[(usize, usize)]) -> Stream<G, (Vec<Node>, i32)> where G: 'a {
-        self.relation_update(0, &description)
+    ///
+    /// Only updates to the first relation are tracked, and so the motif must be connected; no 
+    /// dataflow is constructed otherwise.
+    pub fn build_motif<'a>(&self, motif: &Motif) -> Result<Stream<G, (Vec<Node>, i32)>, MotifError> where G: 'a {
+        let components = motif.components().len();
+        if components != 1 { return Err(MotifError::Disconnected(components)); }
+        let guard = self.guard.as_ref().map(|x| x.query().with_drain(self.forward.drain()));
+        let query = self.fanout.as_ref().map(|x| x.query()).unwrap_or(0);
+        let plan = plan_relation(0, motif.edges(), &PlanOverrides::new()).expect("valid motifs plan without overrides");
+        let mut trips = Vec::new();
+        let result = self.relation_update(query, 0, plan, &guard, &mut trips, &mut HashMap::new());
+        Ok(self.held(match guard {
+            Some(guard) => guard.results(&result, &trips),
+            None => result,
+        }))
+    }
+
+    /// Constructs a dataflow subgraph answering point queries for a described motif from the indices.
+    ///
+    /// Each request `(id, binding)` binds some attributes of the motif, with `binding[a]` the node
+    /// bound to attribute `a`, if any; attributes past the end of `binding` are unbound. A request 
+    /// at a timestamp is answered with every instance agreeing with the binding in the graph as of 
+    /// that timestamp, including its updates, each as `(id, instance)`. Requests only read the 
+    /// indices. A request binding some attribute, but not both attributes of any edge of the motif,
+    /// is first extended through the first relation with a bound attribute, binding its other 
+    /// attribute to each neighbor of the bound node. 
+    ///
+    /// Requests binding no attribute are not answered, and are instead returned as they were received,
+    /// on the second stream, at the timestamp at which they were received.
+    ///
+    /// The motif must be connected; no dataflow is constructed otherwise.
+    pub fn point_queries<'a>(&self, motif: &Motif, requests: &Stream<G, (u64, Vec<Option<Node>>)>) -> Result<(Stream<G, (u64, Vec<Node>)>, Stream<G, (u64, Vec<Option<Node>>)>), MotifError> where G: 'a {
+        let components = motif.components().len();
+        if components != 1 { return Err(MotifError::Disconnected(components)); }
+        let description = motif.edges().to_vec();
+        let relations = description.len();
+        let attributes = motif.attributes();
+
+        // each request is answered by the dataflow of the first relation it binds, if any, and 
+        // otherwise, after extension, by that of the first relation binding one attribute, if any,
+        // and is rejected otherwise.
+        let parts = requests.map(move |(id, binding)| {
+            let mut bound = binding.clone();
+            bound.resize(attributes, None);
+            let seed = description.iter().position(|&(x,y)| bound[x].is_some() && bound[y].is_some())
+                .or_else(|| description.iter().position(|&(x,y)| bound[x].is_some() || bound[y].is_some()).map(|r| relations + r));
+            match seed {
+                Some(seed) => (seed, (id, bound)),
+                None => (2 * relations, (id, binding)),
+            }
+        }).partition(2 * relations as u64 + 1, |(seed, request)| (seed as u64, request));
+
+        let results = (0 .. relations).map(|relation| {
+            self.set_position(Vec::new());
+            let (src, dst) = motif.edges()[relation];
+            let requests = parts[relation].concat(&self.bind_neighbors(&parts[relations + relation], src, dst));
+            let plan = plan_relation(relation, motif.edges(), &PlanOverrides::new()).expect("valid motifs plan without overrides");
+            self.relation_points(plan, &requests)
+        }).collect::<Vec<_>>();
+        Ok((self.held(self.updates.scope().concatenate(results)), parts[2 * relations].clone()))
+    }
+
+    // probes `stream`, the results of queries of an attached dataflow, to hold back merges of the indices.
+    fn held<D: Data>(&self, stream: Stream<G, D>) -> Stream<G, D> {
+        match self.hold {
+            Some(ref hold) => stream.probe_with(&mut hold.clone()),
+            None => stream,
+        }
     }
 }
 
 
-trait IndexNode {
-    fn index(&self, index: usize) -> Node;
+// pairs each instance of `left` with each instance of `right`, whose attributes are `attrs`.
+//
+// Instances of `left` are exchanged by their attribute `key`, and instances of `right` are broadcast,
+// so each worker holds all of them. Changes at each time are combined with the accumulated instances 
+// of the other input, in order of time. A worker that would hold more than `limit` instances of 
+// either input once the changes at a time are applied discards its instances instead, and broadcasts
+// the time, at which every worker stops: the changes of the time and later times are discarded, 
+// and the first stop is recorded in `crossed`. The changes held for each time are reported through 
+// `drain`, if any, at its current position.
+fn cross_product<G: Scope>(left: &Stream<G, (Vec<Node>, i32)>, right: &Stream<G, (Vec<Node>, i32)>, key: usize, attrs: Vec<usize>, limit: usize, crossed: Rc<Cell<Option<CrossLimitExceeded>>>, drain: Option<Drain<G::Timestamp>>) -> Stream<G, (Vec<Node>, i32)>
+    where G::Timestamp: Ord+::std::hash::Hash {
+
+    let drain1 = drain.as_ref().map(|drain| (drain.clone(), drain.register()));
+    let drain2 = drain.map(|drain| { let id = drain.register(); (drain, id) });
+
+    // the times at which any worker has stopped, shared by the product and the release of its changes.
+    let stopped: Rc<RefCell<Vec<G::Timestamp>>> = Rc::new(RefCell::new(Vec::new()));
+    let stopped2 = stopped.clone();
+
+    let mut left_state: HashMap<Vec<Node>, i32> = HashMap::new();
+    let mut right_state: HashMap<Vec<Node>, i32> = HashMap::new();
+    let mut pending: HashMap<G::Timestamp, (Vec<(Vec<Node>, i32)>, Vec<(Vec<Node>, i32)>)> = HashMap::new();
+    let mut buffer1 = Vec::new();
+    let mut buffer2 = Vec::new();
+
+    let combine = move |l: &Vec<Node>, r: &Vec<Node>| {
+        let mut result = l.clone();
+        for &attr in attrs.iter() { result[attr] = r[attr]; }
+        result
+    };
+
+    // changes are produced as `Ok`, and the instances a worker would hold when it stops as `Err`.
+    let exchange = Exchange::new(move |x: &(Vec<Node>, i32)| x.0[key] as u64);
+    let tagged = left.binary_notify(&right.broadcast(), exchange, Pipeline, "CrossProduct", vec![], move |input1, input2, output, notificator| {
+
+        // once any worker has stopped, this worker has completed all earlier times, and stops too.
+        let halted = !stopped.borrow().is_empty();
+        if halted {
+            left_state = HashMap::new();
+            right_state = HashMap::new();
+            pending.clear();
+        }
+
+        input1.for_each(|time, data| {
+            data.swap(&mut buffer1);
+            if halted { buffer1.clear(); return; }
+            pending.entry(time.time().clone()).or_insert((Vec::new(), Vec::new())).0.append(&mut buffer1);
+            notificator.notify_at(time.retain());
+        });
+        input2.for_each(|time, data| {
+            data.swap(&mut buffer2);
+            if halted { buffer2.clear(); return; }
+            pending.entry(time.time().clone()).or_insert((Vec::new(), Vec::new())).1.append(&mut buffer2);
+            notificator.notify_at(time.retain());
+        });
+
+        let mut ready = Vec::new();
+        notificator.for_each(|time, _, _| ready.push(time));
+        ready.sort_by(|x, y| x.time().cmp(y.time()));
+
+        for time in ready {
+            if let Some((left_updates, right_updates)) = pending.remove(time.time()) {
+                let mut session = output.session(&time);
+                let held = ::std::cmp::max(held_after(&left_state, &left_updates), held_after(&right_state, &right_updates));
+                if held > limit {
+                    session.give(Err(held));
+                    stopped.borrow_mut().push(time.time().clone());
+                    left_state = HashMap::new();
+                    right_state = HashMap::new();
+                    pending.clear();
+                    break;
+                }
+                for (l, w) in left_updates {
+                    for (r, v) in right_state.iter() { session.give(Ok((combine(&l, r), w * v))); }
+                    *left_state.entry(l).or_insert(0) += w;
+                }
+                left_state.retain(|_, w| *w != 0);
+                for (r, v) in right_updates {
+                    for (l, w) in left_state.iter() { session.give(Ok((combine(l, &r), w * v))); }
+                    *right_state.entry(r).or_insert(0) += v;
+                }
+                right_state.retain(|_, w| *w != 0);
+            }
+        }
+
+        if let Some((ref drain, id)) = drain1 {
+            drain.set_held(id, pending.keys().cloned().collect());
+        }
+    });
+
+    // changes are released once their time is complete, unless any worker stopped at or before it.
+    let mut held = HashMap::new();
+    let mut buffer = Vec::new();
+    let mut stop_buffer = Vec::new();
+    let stops = tagged.flat_map(|x| x.err()).broadcast();
+    tagged.flat_map(|x| x.ok()).binary_notify(&stops, Pipeline, Pipeline, "CrossLimit", vec![], move |input, stops, output, notificator| {
+
+        stops.for_each(|time, data| {
+            data.swap(&mut stop_buffer);
+            for held in stop_buffer.drain(..) {
+                if crossed.get().is_none() { crossed.set(Some(CrossLimitExceeded { held, limit })); }
+            }
+            let mut stopped = stopped2.borrow_mut();
+            if !stopped.iter().any(|x| x.less_equal(time.time())) { stopped.push(time.time().clone()); }
+        });
+
+        input.for_each(|time, data| {
+            data.swap(&mut buffer);
+            held.entry(time.time().clone()).or_insert(Vec::new()).append(&mut buffer);
+            notificator.notify_at(time.retain());
+        });
+
+        notificator.for_each(|time, _, _| {
+            if let Some(mut changes) = held.remove(time.time()) {
+                if !stopped2.borrow().iter().any(|x| x.less_equal(time.time())) {
+                    output.session(&time).give_vec(&mut changes);
+                }
+            }
+        });
+
+        if let Some((ref drain, id)) = drain2 {
+            drain.set_held(id, held.keys().cloned().collect());
+        }
+    })
 }
 
-impl IndexNode for Vec<Node> {
-    #[inline(always)] fn index(&self, index: usize) -> Node { self[index] }
+// the number of instances `state` would hold once `updates` are applied to it.
+fn held_after(state: &HashMap<Vec<Node>, i32>, updates: &[(Vec<Node>, i32)]) -> usize {
+    let mut changes = HashMap::new();
+    for &(ref instance, weight) in updates.iter() {
+        *changes.entry(instance).or_insert(0) += weight;
+    }
+    let mut held = state.len();
+    for (instance, change) in changes {
+        let before = state.get(instance).cloned().unwrap_or(0);
+        if before == 0 && change != 0 { held += 1; }
+        if before != 0 && before + change == 0 { held -= 1; }
+    }
+    held
 }
-impl IndexNode for [Node; 2] { #[inline(always)] fn index(&self, index: usize) -> Node { self[index] } }
-impl IndexNode for [Node; 3] { #[inline(always)] fn index(&self, index: usize) -> Node { self[index] } }
-impl IndexNode for [Node; 4] { #[inline(always)] fn index(&self, index: usize) -> Node { self[index] } }
-impl IndexNode for [Node; 5] { #[inline(always)] fn index(&self, index: usize) -> Node { self[index] } }
 
 impl<G: Scope, H1: Fn(Node)->u64+'static, H2: Fn(Node)->u64+'static> GraphStreamIndex<G, H1, H2> where G::Timestamp: Ord+::std::hash::Hash {
 
-    // produces updates for changes in the indicated relation only.
-    fn relation_update<'a>(&self, relation: usize, relations: &[(usize, usize)]) -> Stream<G, (Vec<Node>, i32)> 
+    // produces updates for changes in `motif` using `plans` for its relations, as part of the numbered query.
+    fn motif_update<'a>(&self, query: usize, motif: &Motif, plans: Vec<Plan>, guard: &Option<QueryGuard<G::Timestamp>>, trips: &mut Vec<Stream<G, (usize, Edge)>>, trie: &mut StageTrie<G>) -> Stream<G, (Vec<Node>, i32)> 
         where G: 'a {
 
-        // we need to determine an order on the attributes that ensures that each are bound by preceding attributes. 
-        let (attrs, _remap, relations) = order_attributes(relation, &relations);
-        let query_plan = plan_query(&relations, relation);
+        let description = motif.edges();
+        let mut plans = plans.into_iter().map(Some).collect::<Vec<_>>();
+
+        // each component is tracked separately, and combined with those before it by a cross product.
+        let mut result: Option<(Stream<G, (Vec<Node>, i32)>, Vec<usize>)> = None;
+        for component in motif.components() {
+            let mut stream = self.updates.filter(|_| false).map(|_| (Vec::new(), 0));
+            let mut ends = Vec::new();
+            for &relation in component.iter() {
+                let plan = plans[relation].take().expect("relation in two components");
+                stream = stream.concat(&self.relation_update(query, relation, plan, guard, trips, trie));
+                ends.extend(self.position());
+            }
+            result = Some(match result {
+                None => (stream, ends),
+                Some((left, mut left_ends)) => {
+                    let key = description[0].0;
+                    let mut attrs = component.iter().flat_map(|&r| vec![description[r].0, description[r].1]).collect::<Vec<_>>();
+                    attrs.sort();
+                    attrs.dedup();
+                    left_ends.extend(ends);
+                    self.set_position(left_ends);
+                    let stream = cross_product(&left, &stream, key, attrs, self.cross_limit, self.crossed.clone(), self.forward.drain());
+                    (stream, self.position())
+                },
+            });
+        }
+        let (result, ends) = result.expect("motif has no relations");
+        self.set_position(ends);
+        result
+    }
 
+    // positions subsequently constructed operators downstream of `position`, if draining.
+    fn set_position(&self, position: Vec<usize>) {
+        if let Some(drain) = self.forward.drain() { drain.set_position(position); }
+    }
+
+    // the position downstream of which operators are constructed, if draining.
+    fn position(&self) -> Vec<usize> {
+        self.forward.drain().map(|drain| drain.position()).unwrap_or_default()
+    }
+
+    // produces updates for changes in the indicated relation only, as part of the numbered query.
+    //
+    // Stages already in `trie` are reused rather than constructed again, and new stages are added.
+    // The relation's dataflow is independent of others, apart from the stages they share, and
+    // ends at the position of its last stage.
+    fn relation_update<'a>(&self, query: usize, relation: usize, plan: Plan, guard: &Option<QueryGuard<G::Timestamp>>, trips: &mut Vec<Stream<G, (usize, Edge)>>, trie: &mut StageTrie<G>) -> Stream<G, (Vec<Node>, i32)> 
+        where G: 'a {
+
+        // the plan orders attributes so that each is bound by preceding attributes.
+        let keys = stage_keys(&plan);
+        let attributes = plan.attributes;
+        let attrs = plan.attrs;
+        let pins = plan.pins;
+        let query_plan = plan.constraints.into_iter()
+            .map(|stage| stage.into_iter().map(|(_, attr, forward, prior)| (attr, forward, prior)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        self.set_position(Vec::new());
         let source = self.updates.map(|((x,y),w)| ([x, y], w));
-        let stream = if query_plan.len() > 0 {
+        let stream = if !query_plan.is_empty() {
 
             // we do the first extension using arrays rather than vecs, to prove a point.
-            let mut stream = self.extend_attribute(&source, &query_plan[0])
-                                 .flat_map(|(p, es, w)| es.into_iter().map(move |e| (vec![p[0], p[1], e], w)));
+            let stage = |index| Stage { query, relation, stage: index };
+            let (mut stream, position) = trie.entry(keys[.. 1].to_vec()).or_insert_with(|| {
+                let stream = self.guard_stage(&self.extend_attribute(&source, &query_plan[0], pins[0], stage(0)), relation, guard, trips)
+                    .flat_map(|(p, es, w)| es.into_iter().map(move |e| (vec![p[0], p[1], e], w)));
+                (stream, self.position())
+            }).clone();
+            self.set_position(position);
 
             // now stream contains vecs, and so we use vec extensions4.
-            for stage in &query_plan[1..] { 
-                stream = self.extend_attribute(&stream, &stage)
-                             .flat_map(|(p, es, w)|
-                                    es.into_iter().map(move |e|  {
-                                       let mut clone = p.clone();
-                                       clone.push(e);
-                                       (clone, w)
-                                    }));
+            for (index, plan) in query_plan.iter().enumerate().skip(1) { 
+                let prefixes = stream;
+                let (next, position) = trie.entry(keys[.. index + 1].to_vec()).or_insert_with(|| {
+                    let stream = self.guard_stage(&self.extend_attribute(&prefixes, plan, pins[index], stage(index)), relation, guard, trips)
+                        .flat_map(|(p, es, w)|
+                               es.into_iter().map(move |e|  {
+                                  let mut clone = p.clone();
+                                  clone.push(e);
+                                  (clone, w)
+                               }));
+                    (stream, self.position())
+                }).clone();
+                stream = next;
+                self.set_position(position);
             }
 
             stream
@@ -163,9 +861,9 @@ impl<G: Scope, H1: Fn(Node)->u64+'static, H2: Fn(Node)->u64+'static> GraphStream
             source.map(|p| (vec![p.0[0], p.0[1]], p.1))
         };
 
-        // undo the attribute re-ordering.
+        // undo the attribute re-ordering; attributes of other components remain zero.
         stream.map(move |(vec, w)| {
-            let mut new_vec = vec![0; vec.len()];
+            let mut new_vec = vec![0; attributes];
             for (index, &val) in vec.iter().enumerate() {
                 new_vec[attrs[index]] = val;
             }
@@ -173,21 +871,348 @@ impl<G: Scope, H1: Fn(Node)->u64+'static, H2: Fn(Node)->u64+'static> GraphStream
         })
     }
 
+    // counts the extensions of a stage of `relation`'s dataflow against the limits of `guard`, if any,
+    // adding the stage's trips to `trips`, and discards them once the query is cancelled.
+    fn guard_stage<P>(&self, stream: &Stream<G, (P, Vec<Node>, i32)>, relation: usize, guard: &Option<QueryGuard<G::Timestamp>>, trips: &mut Vec<Stream<G, (usize, Edge)>>) -> Stream<G, (P, Vec<Node>, i32)>
+        where P: ExchangeData+Attributes<Node> {
+        let stream = match *guard {
+            // the first two attributes of each prefix are those of the update that produced it.
+            Some(ref guard) => {
+                let (stream, tripped) = guard.stage(stream, relation, |p: &P| (p.attribute(0), p.attribute(1)));
+                trips.push(tripped);
+                stream
+            },
+            None => stream.clone(),
+        };
+        match self.cancel {
+            Some(ref cancel) => cancel.gate(&stream),
+            None => stream,
+        }
+    }
+
+    // binds the unbound attribute of `src` and `dst` in each request to each neighbor of the node
+    // bound to the other, in the graph as of the request's timestamp, including its updates.
+    fn bind_neighbors(&self, requests: &Stream<G, (u64, Vec<Option<Node>>)>, src: usize, dst: usize) -> Stream<G, (u64, Vec<Option<Node>>)> {
+        let parts = requests.partition(2, move |request| {
+            let part = if request.1[src].is_some() { 0 } else { 1 };
+            (part, request)
+        });
+        // the destinations of a bound source are read from the forward index, and the sources of 
+        // a bound destination from the reverse index; each is a branch downstream of the position.
+        let (bound, unbound) = ([src, dst], [dst, src]);
+        let start = self.position();
+        let mut ends = Vec::new();
+        let results = (0 .. 2).map(|part| {
+            let (bound, unbound) = (bound[part], unbound[part]);
+            let prefixes = parts[part].map(move |request| { let node = request.1[bound].unwrap(); ((vec![node], request), 1) });
+            self.set_position(start.clone());
+            let extensions = ::extend_observed(&prefixes, self.extenders(&[(0, part == 0, true)]), None, &());
+            ends.extend(self.position());
+            extensions.flat_map(move |((_, (id, binding)), es, _)| es.into_iter().map(move |e| {
+                let mut binding = binding.clone();
+                binding[unbound] = Some(e);
+                (id, binding)
+            }))
+        }).collect::<Vec<_>>();
+        self.set_position(ends);
+        requests.scope().concatenate(results)
+    }
+
+    // answers point queries whose bindings bind the plan's first two attributes, by extending them
+    // with the remaining attributes or checking the nodes bound to them, at each stage.
+    fn relation_points<'a>(&self, plan: Plan, requests: &Stream<G, (u64, Vec<Option<Node>>)>) -> Stream<G, (u64, Vec<Node>)> 
+        where G: 'a {
+
+        // every relation is read in its current state, including updates at the request's timestamp.
+        let attrs = plan.attrs;
+        let query_plan = plan.constraints.into_iter()
+            .map(|stage| stage.into_iter().map(|(_, attr, forward, _)| (attr, forward, true)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // the bound edge must be present, which the forward index checks.
+        let (src, dst) = (attrs[0], attrs[1]);
+        let edges = requests.map(move |request| {
+            let (x, y) = (request.1[src].unwrap(), request.1[dst].unwrap());
+            ((vec![x], request), vec![y], 1)
+        });
+        let mut stream = self.intersect_attribute(&edges, &[(0, true, true)])
+            .flat_map(|((p, request), es, w)| es.into_iter().map(move |e| {
+                let mut clone = p.clone();
+                clone.push(e);
+                ((clone, request.clone()), w)
+            }));
+
+        for (index, plan) in query_plan.iter().enumerate() {
+            // nodes bound by the request are checked against the constraints, and others proposed.
+            let attribute = attrs[index + 2];
+            let parts = stream.partition(2, move |(p, w)| {
+                let part = if (p.1).1[attribute].is_some() { 0 } else { 1 };
+                (part, (p, w))
+            });
+            let bound = parts[0].map(move |(p, w)| { let node = (p.1).1[attribute].unwrap(); (p, vec![node], w) });
+            let start = self.position();
+            let bound = self.intersect_attribute(&bound, plan);
+            let mut ends = self.position();
+            self.set_position(start);
+            let unbound = ::extend_observed(&parts[1], self.extenders(plan), None, &());
+            ends.extend(self.position());
+            self.set_position(ends);
+            stream = bound.concat(&unbound)
+                .flat_map(|((p, request), es, w)| es.into_iter().map(move |e| {
+                    let mut clone = p.clone();
+                    clone.push(e);
+                    ((clone, request.clone()), w)
+                }));
+        }
+
+        // undo the attribute re-ordering, and tag each instance with its request.
+        stream.map(move |((vec, (id, _)), _)| {
+            let mut new_vec = vec![0; vec.len()];
+            for (index, &val) in vec.iter().enumerate() {
+                new_vec[attrs[index]] = val;
+            }
+            (id, new_vec)
+        })
+    }
+
     /// Extends an indexable prefix, using a plan described by several (attr, is_forward, is_prior) cues.
-    fn extend_attribute<'a, P>(&self, stream: &Stream<G, (P, i32)>, plan: &[(usize, bool, bool)]) -> Stream<G, (P, Vec<u32>, i32)> 
+    ///
+    /// If `pin` is set, the indicated cue proposes all extensions. If a `Fanout` is set, the work of 
+    /// the extension is counted as that of `stage`.
+    fn extend_attribute<'a, P>(&self, stream: &Stream<G, (P, i32)>, plan: &[(usize, bool, bool)], pin: Option<usize>, stage: Stage) -> Stream<G, (P, Vec<u32>, i32)> 
         where G: 'a,
-              P: ::std::fmt::Debug+ExchangeData+IndexNode {
-        let mut extenders: Vec<Box<StreamPrefixExtender<G, i32, Prefix=P, Extension=Node>+'a>> = vec![];
+              P: ::std::fmt::Debug+ExchangeData+Attributes<Node> {
+        let extenders = self.extenders(plan);
+        if self.fanout.is_some() {
+            let observer = StageObserver::new(stage);
+            let extensions = ::extend_observed(stream, extenders, pin, &observer);
+            self.counts.borrow_mut().push(observer.counts(&stream.scope()));
+            extensions
+        }
+        else {
+            ::extend_observed(stream, extenders, pin, &())
+        }
+    }
+
+    // restricts proposed extensions of indexable prefixes to those each of several cues would propose.
+    fn intersect_attribute<'a, P>(&self, stream: &Stream<G, (P, Vec<Node>, i32)>, plan: &[(usize, bool, bool)]) -> Stream<G, (P, Vec<Node>, i32)> 
+        where G: 'a,
+              P: ::std::fmt::Debug+ExchangeData+Attributes<Node> {
+        let mut stream = stream.clone();
+        for extender in self.extenders(plan) {
+            stream = extender.intersect(stream);
+        }
+        stream
+    }
+
+    // constructs an extender for each of several (attr, is_forward, is_prior) cues.
+    fn extenders<'a, P>(&self, plan: &[(usize, bool, bool)]) -> Vec<Box<dyn StreamPrefixExtender<G, i32, Prefix=P, Extension=Node>+'a>> 
+        where G: 'a,
+              P: ::std::fmt::Debug+ExchangeData+Attributes<Node> {
+        let mut extenders: Vec<Box<dyn StreamPrefixExtender<G, i32, Prefix=P, Extension=Node>+'a>> = vec![];
         for &(attribute, is_forward, prior) in plan {
             extenders.push(match (is_forward, prior) {
-                (true, true)    => Box::new(self.forward.extend_using(move |x: &P| x.index(attribute), <_ as PartialOrd>::le)),
-                (true, false)   => Box::new(self.forward.extend_using(move |x: &P| x.index(attribute), <_ as PartialOrd>::lt)),
-                (false, true)   => Box::new(self.reverse.extend_using(move |x: &P| x.index(attribute), <_ as PartialOrd>::le)),
-                (false, false)  => Box::new(self.reverse.extend_using(move |x: &P| x.index(attribute), <_ as PartialOrd>::lt)),
+                (true, true)    => Box::new(self.forward.extend_using_attribute(attribute, <_ as PartialOrd>::le)),
+                (true, false)   => Box::new(self.forward.extend_using_attribute(attribute, <_ as PartialOrd>::lt)),
+                (false, true)   => Box::new(self.reverse.extend_using_attribute(attribute, <_ as PartialOrd>::le)),
+                (false, false)  => Box::new(self.reverse.extend_using_attribute(attribute, <_ as PartialOrd>::lt)),
             })
         }
-        stream.extend(extenders)
+        extenders
+    }
+}
+
+impl<G: Scope, H: Fn(Node)->u64+'static> GraphStreamIndex<G, H, H> where G::Timestamp: Ord+::std::hash::Hash {
+
+    /// Constructs a new graph stream index whose forward and reverse indices share `hash`.
+    ///
+    /// As both indices place nodes identically, consecutive extensions of the same attribute 
+    /// need not exchange prefixes between them.
+    pub fn from_copartitioned(initially: Stream<G, Edge>, 
+                updates: Stream<G, (Edge, i32)>, hash: H) -> (Self, GraphStreamIndexHandle<G::Timestamp>) {
+
+        let forward = IndexStream::from_backend(Index::new().with_simd(), hash, &initially, &updates);
+        let reverse = forward.copartitioned(Index::new().with_simd(), &initially.map(|(src,dst)| (dst,src)),
+                                                          &updates.map(|((src,dst),wgt)| ((dst,src),wgt)));
+        Self::assemble(updates, forward, reverse)
+    }
+}
+
+/// Changes to the plans `track_motif_with` would otherwise choose.
+///
+/// Each relation of a motif has its own dataflow, which binds the relation's attributes from each 
+/// update and then binds the remaining attributes one at a time. An override may replace the order
+/// in which a relation's dataflow binds attributes, and may pin the relation whose index proposes 
+/// the extensions for an attribute, rather than using the index with the fewest for each prefix.
+#[derive(Clone, Debug, Default)]
+pub struct PlanOverrides {
+    orders: HashMap<usize, Vec<usize>>,
+    pins: HashMap<(usize, usize), usize>,
+}
+
+impl PlanOverrides {
+    /// Allocates overrides which change nothing.
+    pub fn new() -> Self { PlanOverrides { orders: HashMap::new(), pins: HashMap::new() } }
+
+    /// Binds the attributes of `relation`'s dataflow in `order`.
+    ///
+    /// The order must list every attribute, start with the source and destination of `relation`, 
+    /// and bind each further attribute through a relation with an attribute before it.
+    pub fn order(mut self, relation: usize, order: Vec<usize>) -> Self {
+        self.orders.insert(relation, order);
+        self
+    }
+
+    /// Proposes extensions for `attribute` in `relation`'s dataflow from `proposer`'s index.
+    ///
+    /// The relation `proposer` must relate `attribute` to an attribute bound before it.
+    pub fn pin(mut self, relation: usize, attribute: usize, proposer: usize) -> Self {
+        self.pins.insert((relation, attribute), proposer);
+        self
+    }
+}
+
+/// The reason a motif could not be planned as overridden.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    /// An override names a relation not in the motif.
+    NoSuchRelation(usize),
+    /// The order for `relation` does not list each attribute connected to the relation exactly once.
+    NotAnOrder { relation: usize, order: Vec<usize> },
+    /// The order for `relation` does not start with the relation's source and destination.
+    WrongStart { relation: usize, order: Vec<usize> },
+    /// The order for `relation` binds `attribute` before any relation connects it to an earlier attribute.
+    Unbound { relation: usize, attribute: usize },
+    /// A pin for `relation` names an `attribute` bound by the update, rather than by a stage.
+    NotExtended { relation: usize, attribute: usize },
+    /// A pin for `relation` names an `attribute` not connected to the relation, which its dataflow never binds.
+    NotConnected { relation: usize, attribute: usize },
+    /// A pin for `relation` names a `proposer` which does not constrain `attribute` by an earlier attribute.
+    NotConstraint { relation: usize, attribute: usize, proposer: usize },
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlanError::NoSuchRelation(relation) => 
+                write!(f, "override names relation {}, which is not in the motif", relation),
+            PlanError::NotAnOrder { relation, ref order } => 
+                write!(f, "order {:?} for relation {} does not list each attribute connected to it exactly once", order, relation),
+            PlanError::WrongStart { relation, ref order } => 
+                write!(f, "order {:?} for relation {} does not start with the relation's attributes", order, relation),
+            PlanError::Unbound { relation, attribute } => 
+                write!(f, "order for relation {} binds x{} before a relation connects it to an earlier attribute", relation, attribute),
+            PlanError::NotExtended { relation, attribute } => 
+                write!(f, "pin for relation {} names x{}, which is bound by the update", relation, attribute),
+            PlanError::NotConnected { relation, attribute } => 
+                write!(f, "pin for relation {} names x{}, which is not connected to the relation", relation, attribute),
+            PlanError::NotConstraint { relation, attribute, proposer } => 
+                write!(f, "pin for relation {} names relation {}, which does not bind x{} from an earlier attribute", relation, proposer, attribute),
+        }
+    }
+}
+
+impl Error for PlanError { }
+
+// identifies a stage by its constraints, as sorted (attr, is_forward, is_prior) cues, and its pinned cue.
+type StageKey = (Vec<(usize, bool, bool)>, Option<(usize, bool, bool)>);
+
+// the streams of prefixes produced by each sequence of stages, and the `Drain` position after each.
+type StageTrie<G> = HashMap<Vec<StageKey>, (Stream<G, (Vec<Node>, i32)>, Vec<usize>)>;
+
+// the keys of the stages of `plan`.
+//
+// Two sequences of stages with the same keys produce the same prefixes, as they start from the same
+// updates and each stage binds the next position of the prefix with the same constraints.
+fn stage_keys(plan: &Plan) -> Vec<StageKey> {
+    plan.constraints.iter().zip(plan.pins.iter()).map(|(constraints, pin)| {
+        let mut cues = constraints.iter().map(|&(_, attr, forward, prior)| (attr, forward, prior)).collect::<Vec<_>>();
+        let pinned = pin.map(|position| cues[position]);
+        cues.sort();
+        (cues, pinned)
+    }).collect()
+}
+
+/// The numbers of stages in the dataflows of `motifs`, when constructed separately and when shared 
+/// by `track_motifs`, respectively.
+pub fn shared_stages(motifs: &[Motif]) -> (usize, usize) {
+    let mut separate = 0;
+    let mut shared = ::std::collections::HashSet::new();
+    for motif in motifs.iter() {
+        for relation in 0 .. motif.edges().len() {
+            let plan = plan_relation(relation, motif.edges(), &PlanOverrides::new()).expect("valid motifs plan without overrides");
+            let keys = stage_keys(&plan);
+            separate += keys.len();
+            for depth in 1 .. keys.len() + 1 {
+                shared.insert(keys[.. depth].to_vec());
+            }
+        }
     }
+    (separate, shared.len())
+}
+
+// the attribute order and constraints for `relation`'s dataflow, and the position of the pinned 
+// constraint of each stage, if any.
+struct Plan {
+    attributes: usize,
+    attrs: Vec<usize>,
+    constraints: Vec<Vec<(usize, usize, bool, bool)>>,
+    pins: Vec<Option<usize>>,
+}
+
+// plans `relation`'s dataflow for `description`, as changed by `overrides`.
+fn plan_relation(relation: usize, description: &[(usize, usize)], overrides: &PlanOverrides) -> Result<Plan, PlanError> {
+
+    let keys = overrides.orders.keys().cloned().chain(overrides.pins.keys().map(|x| x.0));
+    for other in keys.chain(overrides.pins.values().cloned()) {
+        if other >= description.len() { return Err(PlanError::NoSuchRelation(other)); }
+    }
+
+    let (attrs, relations) = match overrides.orders.get(&relation) {
+        Some(order) => {
+            let mut component = order_attributes(relation, description).0;
+            component.sort();
+            let mut sorted = order.clone();
+            sorted.sort();
+            if sorted != component {
+                return Err(PlanError::NotAnOrder { relation, order: order.clone() });
+            }
+            // the update binds the first two attributes, whose relation must not be read again.
+            if order[0] != description[relation].0 || order[1] != description[relation].1 {
+                return Err(PlanError::WrongStart { relation, order: order.clone() });
+            }
+            for position in 2 .. order.len() {
+                let bound = description.iter().any(|&(src, dst)| {
+                    (src == order[position] && order[.. position].contains(&dst)) ||
+                    (dst == order[position] && order[.. position].contains(&src))
+                });
+                if !bound { return Err(PlanError::Unbound { relation, attribute: order[position] }); }
+            }
+            let (_relabel, relations) = relabel_attributes(order, description);
+            (order.clone(), relations)
+        },
+        None => {
+            let (attrs, _relabel, relations) = order_attributes(relation, description);
+            (attrs, relations)
+        },
+    };
+
+    let constraints = plan_query(&relations, relation, attrs.len());
+    let mut pins = vec![None; constraints.len()];
+    for (&(pinned, attribute), &proposer) in overrides.pins.iter().filter(|x| (x.0).0 == relation) {
+        let position = match attrs.iter().position(|&x| x == attribute) {
+            Some(position) => position,
+            None => { return Err(PlanError::NotConnected { relation: pinned, attribute }); },
+        };
+        if position < 2 { return Err(PlanError::NotExtended { relation: pinned, attribute }); }
+        match constraints[position - 2].iter().position(|x| x.0 == proposer) {
+            Some(index) => { pins[position - 2] = Some(index); },
+            None => { return Err(PlanError::NotConstraint { relation: pinned, attribute, proposer }); },
+        }
+    }
+
+    let attributes = description.iter().map(|&(x,y)| ::std::cmp::max(x, y) + 1).max().unwrap_or(0);
+    Ok(Plan { attributes, attrs, constraints, pins })
 }
 
 // orders the numbers 0 .. so that each has at least one relation binding it to a prior attribute, 
@@ -217,33 +1242,44 @@ fn order_attributes(relation_index: usize, relations: &[(usize, usize)]) -> (Vec
         }
     }
 
+    let (relabel, relations) = relabel_attributes(&active, relations);
+    (active, relabel, relations)
+}
+
+// relabels the attributes of `relations` by their positions in `active`.
+//
+// Attributes not in `active`, those of other components of a disconnected motif, are relabeled
+// after those in `active`, and so are never bound by stages planned for `active`.
+fn relabel_attributes(active: &[usize], relations: &[(usize, usize)]) -> (Vec<usize>, Vec<(usize, usize)>) {
+
     // 2. Re-map each of the relations to treat attributes in order, avoiding weird re-indexing later on.
-    let mut relabel = vec![0; active.len()];
+    let attributes = relations.iter().map(|&(x,y)| ::std::cmp::max(x, y) + 1).max().unwrap_or(0);
+    let mut relabel = vec![0; attributes];
     for (position, &attribute) in active.iter().enumerate() {
     	relabel[attribute] = position;
     }
+    let inactive = (0 .. attributes).filter(|x| !active.contains(x));
+    for (position, attribute) in (active.len() ..).zip(inactive) {
+        relabel[attribute] = position;
+    }
 
     let relations = relations.iter().map(|&(src,dst)| (relabel[src], relabel[dst])).collect::<Vec<_>>();
 
-    // 3. Return the attribute order, the relabeling, and the relabeled relations
-    (active, relabel, relations)
+    // 3. Return the relabeling, and the relabeled relations
+    (relabel, relations)
 }
 
 /// Determines constraints on each of a sequence of attributes.
 ///
 /// Given relations on attributes, presumed to be introduced in increasing order, this method identifies 
-/// for each attribute the constraints on it in terms of triples of
+/// for each attribute the constraints on it in terms of quadruples of
+///    0. the index of the constraining relation,
 ///    1. prior attributes, 
 ///    2. which index is required (forward: true, reverse: false), and 
 ///    3. whether the relation comes before or after `source_index`.
-fn plan_query(relations: &[(usize, usize)], source_index: usize) -> Vec<Vec<(usize, bool, bool)>> {
-
-	let mut attributes = 0;
-	for &(src,dst) in relations {
-		if attributes < src { attributes = src; }
-		if attributes < dst { attributes = dst; }
-	}
-	attributes += 1;
+///
+/// Only the first `attributes` attributes are planned, which are those connected to `source_index`.
+fn plan_query(relations: &[(usize, usize)], source_index: usize, attributes: usize) -> Vec<Vec<(usize, usize, bool, bool)>> {
 
 	// for each attribute, determine relations constraining that attribute.
 	let mut plan = vec![];
@@ -252,15 +1288,227 @@ fn plan_query(relations: &[(usize, usize)], source_index: usize) -> Vec<Vec<(usi
 		for (index, &(src, dst)) in relations.iter().enumerate() {
 			// if src is our attribute and dst is already bound ...
 			if src == attribute && dst < attribute {
-				constraints.push((dst, false, index < source_index));
+				constraints.push((index, dst, false, index < source_index));
 			}
 			// if dst is our attribute and src is already bound ...
 			if dst == attribute && src < attribute {
-				constraints.push((src, true, index < source_index));
+				constraints.push((index, src, true, index < source_index));
 			}
 		}
 		plan.push(constraints);
 	}
 
 	plan
-}
\ No newline at end of file
+}
+
+/// A constraint on an attribute introduced by a stage of a relation's dataflow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintPlan {
+    /// The relation, as an index into the motif description.
+    pub relation: usize,
+    /// The attribute already bound, which the relation relates to the new attribute.
+    pub bound: usize,
+    /// True if the forward index is used, keyed by the relation's source, or false for the reverse index.
+    pub forward: bool,
+    /// True if the relation precedes the updated relation, and so reads index state including the
+    /// updates at the same time (`le`), rather than only those before it (`lt`).
+    pub prior: bool,
+}
+
+/// A stage of a relation's dataflow, which binds one more attribute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StagePlan {
+    /// The attribute the stage binds.
+    pub attribute: usize,
+    /// The constraints on the attribute, each of which becomes an extender.
+    pub constraints: Vec<ConstraintPlan>,
+    /// The relation of the constraint that proposes all extensions, if pinned by an override.
+    pub pinned: Option<usize>,
+}
+
+/// The dataflow that produces changes to a motif for updates to one of its relations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelationPlan {
+    /// The updated relation, as an index into the motif description.
+    pub relation: usize,
+    /// The connected component of the motif containing the relation, numbered as by `Motif::components`.
+    pub component: usize,
+    /// The order in which attributes are bound, starting with the updated relation's.
+    pub order: Vec<usize>,
+    /// The stages that bind each attribute after the first two, in order.
+    pub stages: Vec<StagePlan>,
+}
+
+/// Describes the dataflow `track_motif` constructs for `motif`, one plan for each relation.
+///
+/// Attributes are reported as they are numbered in `motif`.
+pub fn explain(motif: &Motif) -> Vec<RelationPlan> {
+    explain_with(motif, &PlanOverrides::new()).expect("valid motifs plan without overrides")
+}
+
+/// Describes the dataflow `track_motif_with` constructs for `motif` and `overrides`.
+pub fn explain_with(motif: &Motif, overrides: &PlanOverrides) -> Result<Vec<RelationPlan>, PlanError> {
+    let description = motif.edges();
+    let components = motif.components();
+    (0 .. description.len()).map(|relation| {
+        let component = components.iter().position(|x| x.contains(&relation)).expect("relation in no component");
+        let plan = plan_relation(relation, description, overrides)?;
+        let attrs = plan.attrs;
+        let stages = plan.constraints
+            .into_iter()
+            .zip(plan.pins)
+            .enumerate()
+            .map(|(index, (constraints, pin))| StagePlan {
+                attribute: attrs[index + 2],
+                pinned: pin.map(|position| constraints[position].0),
+                constraints: constraints.into_iter().map(|(relation, bound, forward, prior)| ConstraintPlan {
+                    relation,
+                    bound: attrs[bound],
+                    forward,
+                    prior,
+                }).collect(),
+            })
+            .collect();
+        Ok(RelationPlan { relation, component, order: attrs, stages })
+    }).collect()
+}
+
+impl ::std::fmt::Display for RelationPlan {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        writeln!(f, "relation {} (component {}): order {:?}", self.relation, self.component, self.order)?;
+        for (index, stage) in self.stages.iter().enumerate() {
+            match stage.pinned {
+                Some(relation) => writeln!(f, "  stage {}: bind x{}, proposed by relation {}", index, stage.attribute, relation)?,
+                None => writeln!(f, "  stage {}: bind x{}", index, stage.attribute)?,
+            }
+            for constraint in stage.constraints.iter() {
+                writeln!(f, "    from x{} by relation {}, {} index, {}",
+                         constraint.bound,
+                         constraint.relation,
+                         if constraint.forward { "forward" } else { "reverse" },
+                         if constraint.prior { "le" } else { "lt" })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders the dataflow described by `plans` in Graphviz DOT.
+///
+/// Each relation's dataflow is a cluster of its stages, each labeled by the extenders it uses, 
+/// and all of them read the same updates. The results of each component of the motif are 
+/// concatenated, and then combined by cross products if there are several components.
+pub fn explain_dot(plans: &[RelationPlan]) -> String {
+    let mut dot = String::new();
+    dot.push_str("digraph motif {\n");
+    dot.push_str("  node [shape=box];\n");
+    dot.push_str("  updates [label=\"updates\"];\n");
+    dot.push_str("  results [label=\"results\"];\n");
+    for plan in plans.iter() {
+        let r = plan.relation;
+        dot.push_str(&format!("  subgraph cluster_{} {{\n", r));
+        dot.push_str(&format!("    label=\"relation {}, order {:?}\";\n", r, plan.order));
+        dot.push_str(&format!("    r{}_source [label=\"(x{}, x{})\"];\n", r, plan.order[0], plan.order[1]));
+        for (index, stage) in plan.stages.iter().enumerate() {
+            let mut label = format!("bind x{}", stage.attribute);
+            if let Some(relation) = stage.pinned {
+                label.push_str(&format!(", proposed by relation {}", relation));
+            }
+            for constraint in stage.constraints.iter() {
+                label.push_str(&format!("\\n{} x{} (relation {}, {})",
+                                        if constraint.forward { "forward" } else { "reverse" },
+                                        constraint.bound,
+                                        constraint.relation,
+                                        if constraint.prior { "le" } else { "lt" }));
+            }
+            dot.push_str(&format!("    r{}_s{} [label=\"{}\"];\n", r, index, label));
+        }
+        dot.push_str("  }\n");
+        dot.push_str(&format!("  updates -> r{}_source;\n", r));
+        let mut last = format!("r{}_source", r);
+        for index in 0 .. plan.stages.len() {
+            let next = format!("r{}_s{}", r, index);
+            dot.push_str(&format!("  {} -> {};\n", last, next));
+            last = next;
+        }
+        dot.push_str(&format!("  {} -> component{};\n", last, plan.component));
+    }
+    let components = plans.iter().map(|x| x.component + 1).max().unwrap_or(0);
+    for component in 0 .. components {
+        dot.push_str(&format!("  component{} [label=\"component {}\"];\n", component, component));
+    }
+    let mut last = "component0".to_owned();
+    for component in 1 .. components {
+        dot.push_str(&format!("  cross{} [label=\"cross product\"];\n", component));
+        dot.push_str(&format!("  {} -> cross{};\n", last, component));
+        dot.push_str(&format!("  component{} -> cross{};\n", component, component));
+        last = format!("cross{}", component);
+    }
+    if components > 0 {
+        dot.push_str(&format!("  {} -> results;\n", last));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TRIANGLE: [(usize, usize); 3] = [(0, 1), (1, 2), (0, 2)];
+
+    // the error planning `relation` of `description` with `overrides`, if any.
+    fn plan_error(relation: usize, description: &[(usize, usize)], overrides: PlanOverrides) -> Option<PlanError> {
+        plan_relation(relation, description, &overrides).err()
+    }
+
+    #[test]
+    fn plan_overrides() {
+        let plan = match plan_relation(0, &TRIANGLE, &PlanOverrides::new().order(0, vec![0, 1, 2]).pin(0, 2, 2)) {
+            Ok(plan) => plan,
+            Err(error) => panic!("valid overrides refused: {}", error),
+        };
+        assert_eq!(plan.attrs, vec![0, 1, 2]);
+        assert_eq!(plan.pins.len(), 1);
+        assert_eq!(plan.pins[0].map(|index| plan.constraints[0][index].0), Some(2));
+
+        // overrides of other relations are checked only for the relations they name.
+        assert!(plan_relation(1, &TRIANGLE, &PlanOverrides::new().pin(0, 1, 1)).is_ok());
+    }
+
+    #[test]
+    fn plan_override_errors() {
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().order(5, vec![0, 1, 2])), Some(PlanError::NoSuchRelation(5)));
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().pin(0, 2, 7)), Some(PlanError::NoSuchRelation(7)));
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().order(0, vec![0, 1])), Some(PlanError::NotAnOrder { relation: 0, order: vec![0, 1] }));
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().order(0, vec![0, 1, 2, 2])), Some(PlanError::NotAnOrder { relation: 0, order: vec![0, 1, 2, 2] }));
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().order(0, vec![1, 0, 2])), Some(PlanError::WrongStart { relation: 0, order: vec![1, 0, 2] }));
+        assert_eq!(plan_error(0, &[(0, 1), (1, 2), (2, 3)], PlanOverrides::new().order(0, vec![0, 1, 3, 2])), Some(PlanError::Unbound { relation: 0, attribute: 3 }));
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().pin(0, 1, 1)), Some(PlanError::NotExtended { relation: 0, attribute: 1 }));
+        assert_eq!(plan_error(0, &[(0, 1), (2, 3)], PlanOverrides::new().pin(0, 2, 1)), Some(PlanError::NotConnected { relation: 0, attribute: 2 }));
+        assert_eq!(plan_error(0, &TRIANGLE, PlanOverrides::new().pin(0, 2, 0)), Some(PlanError::NotConstraint { relation: 0, attribute: 2, proposer: 0 }));
+    }
+
+    #[test]
+    fn motif_validation() {
+        let motif = Motif::new(&TRIANGLE).expect("valid motif");
+        assert_eq!((motif.edges(), motif.attributes()), (&TRIANGLE[..], 3));
+        assert_eq!(motif.components(), vec![vec![0, 1, 2]]);
+        assert!(motif.warnings().is_empty());
+
+        assert_eq!(Motif::new(&[]), Err(MotifError::Empty));
+        assert_eq!(Motif::new(&[(0, 1), (2, 2)]), Err(MotifError::SelfLoop(2, 2)));
+        assert_eq!(Motif::new(&[(0, 1), (1, 2), (0, 1)]), Err(MotifError::Duplicate(0, 1)));
+        assert_eq!(Motif::new(&[(0, 1), (1, 2), (2, 1)]), Err(MotifError::Reciprocal(2, 1)));
+        assert_eq!(Motif::new(&[(0, 1), (1, 3)]), Err(MotifError::Unused(2)));
+        assert_eq!(Motif::new(&[(1, 2)]), Err(MotifError::Unused(0)));
+    }
+
+    #[test]
+    fn disconnected_motifs() {
+        let motif = Motif::new(&[(0, 1), (2, 3), (1, 4)]).expect("valid motif");
+        assert_eq!(motif.components(), vec![vec![0, 2], vec![1]]);
+        assert_eq!(motif.warnings(), vec![MotifWarning::CrossProduct { left: vec![0, 1, 4], right: vec![2, 3] }]);
+    }
+}
@@ -0,0 +1,189 @@
+//! Partitioning functions for the keys of an `IndexStream`.
+//!
+//! An `IndexStream` places each key at the worker indicated by its hash function, modulo the
+//! number of workers. The identity-like `|k| k as u64` is simple, but places keys by their low
+//! bits, which can correlate with their degree when identifiers have been assigned by degree.
+//! The functions here produce alternatives, each of which can be passed wherever a hash function
+//! is expected, for example to `IndexStream::from` or `GraphStreamIndex::from`.
+//!
+//! Each worker must use the same function for the same index, so any state used to construct a
+//! partitioner (range bounds, degrees) must be identical at each worker.
+//!
+//! The `report` function gathers the number of keys and values each worker holds, so that any
+//! imbalance of a partitioner is visible.
+//!
+//! Note that `IndexStream::from_dense` requires keys be placed by `key % peers`, and so cannot be
+//! used with these partitioners.
+
+use std::hash::{Hash, Hasher};
+use std::collections::HashMap;
+
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::operators::Operator;
+
+/// A hasher whose output bits all depend on all input bits.
+///
+/// Bytes are combined as in FNV-1a, and the result is finished with the avalanching mixer
+/// of SplitMix64. Unlike `DefaultHasher`, the output is specified, and so is the same for all
+/// workers and processes.
+pub struct MixHasher {
+    state: u64,
+}
+
+impl Default for MixHasher {
+    fn default() -> Self { MixHasher::new() }
+}
+
+impl MixHasher {
+    /// Allocates a new `MixHasher`.
+    pub fn new() -> Self { MixHasher { state: 0xcbf29ce484222325 } }
+}
+
+impl Hasher for MixHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= byte as u64;
+            self.state = self.state.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// A well-mixed hash of `key`, suitable for placing keys uniformly across workers.
+pub fn mix<K: Hash>(key: K) -> u64 {
+    let mut hasher = MixHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Places keys by range, so that worker `i` holds keys from `bounds[i-1]` up to `bounds[i]`.
+///
+/// The `bounds` must be sorted, and should contain one fewer element than there are workers.
+/// Each bound is the first key of the next range; the last worker holds all keys from the
+/// last bound onwards.
+pub fn range<K: Ord>(bounds: Vec<K>) -> impl Fn(K)->u64 {
+    assert!(bounds.windows(2).all(|x| x[0] <= x[1]));
+    move |key| {
+        match bounds.binary_search(&key) {
+            Ok(position) => position as u64 + 1,
+            Err(position) => position as u64,
+        }
+    }
+}
+
+/// Splits the keys `0 .. keys` into `peers` contiguous ranges of nearly equal size.
+///
+/// The result is suitable for `range`.
+pub fn even_bounds(keys: u64, peers: usize) -> Vec<u64> {
+    (1 .. peers as u64).map(|part| keys * part / peers as u64).collect()
+}
+
+/// Places keys so that the total degree at each worker is balanced.
+///
+/// Keys in `degrees` are assigned in decreasing order of degree, each to the worker with the
+/// least total degree so far (ties broken by key, then by worker). Keys not in `degrees` are
+/// placed by `mix`, and so it suffices to list the keys of highest degree.
+pub fn balanced<K: Ord+Hash+Clone>(degrees: &[(K, usize)], peers: usize) -> impl Fn(K)->u64 {
+
+    let mut sorted = degrees.to_vec();
+    sorted.sort_by(|x,y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)));
+
+    let mut loads = vec![0usize; peers];
+    let mut assignment = HashMap::new();
+    for (key, degree) in sorted {
+        let worker = (0 .. peers).min_by_key(|&worker| (loads[worker], worker)).unwrap();
+        loads[worker] += degree;
+        assignment.insert(key, worker as u64);
+    }
+
+    move |key| {
+        match assignment.get(&key) {
+            Some(&worker) => worker,
+            None => mix(key),
+        }
+    }
+}
+
+/// The keys and values one worker holds in its part of an index.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Load {
+    /// The reporting worker.
+    pub worker: usize,
+    /// Number of keys held by the worker.
+    pub keys: usize,
+    /// Number of values held by the worker.
+    pub values: usize,
+}
+
+/// Gathers at worker zero the `(worker, keys, values)` each worker sends, as a `Load` per worker.
+///
+/// Each worker should send the counts of its own part of an index, for example from `IndexStats::keys` 
+/// and `IndexStats::values`. For each time, worker zero produces the loads of all workers that sent 
+/// counts at that time, ordered by worker; other workers produce nothing.
+pub fn report<G: Scope>(counts: &Stream<G, (usize, usize, usize)>) -> Stream<G, Vec<Load>> where G::Timestamp: Hash {
+    let mut loads = HashMap::new();
+    let mut buffer = Vec::new();
+    counts.unary_notify(Exchange::new(|_| 0), "PartitionReport", vec![], move |input, output, notificator| {
+        input.for_each(|time, data| {
+            data.swap(&mut buffer);
+            let load = loads.entry(time.time().clone()).or_insert_with(Vec::new);
+            load.extend(buffer.drain(..).map(|(worker, keys, values)| Load { worker, keys, values }));
+            notificator.notify_at(time.retain());
+        });
+        notificator.for_each(|time,_,_| {
+            if let Some(mut load) = loads.remove(time.time()) {
+                load.sort_by_key(|x| x.worker);
+                output.session(&time).give(load);
+            }
+        });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn range_places_bounds_with_the_next_range() {
+        let place = range(vec![10, 20, 30]);
+        let placed = [0, 9, 10, 19, 20, 29, 30, 1000].iter().map(|&key| place(key)).collect::<Vec<_>>();
+        assert_eq!(placed, vec![0, 0, 1, 1, 2, 2, 3, 3]);
+
+        // a single worker holds every key.
+        let place = range(Vec::<u32>::new());
+        assert_eq!((place(0), place(u32::MAX)), (0, 0));
+    }
+
+    #[test]
+    fn even_bounds_split_evenly() {
+        assert_eq!(even_bounds(100, 4), vec![25, 50, 75]);
+        assert_eq!(even_bounds(10, 3), vec![3, 6]);
+        assert_eq!(even_bounds(10, 1), Vec::<u64>::new());
+
+        // each worker receives the same number of keys, give or take one.
+        let place = range(even_bounds(1000, 7));
+        let mut counts = vec![0; 7];
+        for key in 0 .. 1000 { counts[place(key) as usize] += 1; }
+        assert!(counts.iter().all(|&count| count == 142 || count == 143), "{:?}", counts);
+    }
+
+    #[test]
+    fn balanced_assigns_largest_degrees_first() {
+        let place = balanced(&[("d", 1), ("b", 6), ("a", 10), ("c", 5)], 2);
+        // "a" to worker zero, then "b" and "c" to worker one, then "d" to worker zero.
+        assert_eq!((place("a"), place("b"), place("c"), place("d")), (0, 1, 1, 0));
+        // keys without listed degrees are mixed.
+        assert_eq!(place("e"), mix("e"));
+
+        // ties in degree are broken by key, and ties in load by worker.
+        let place = balanced(&[(3, 4), (1, 4), (2, 4)], 3);
+        assert_eq!((place(1), place(2), place(3)), (0, 1, 2));
+    }
+}
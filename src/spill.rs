@@ -0,0 +1,170 @@
+//! Spilling of blocked records to local disk.
+//!
+//! The extender operators hold prefixes that cannot yet be processed, because the index has not
+//! yet accepted all updates at or before their time. If the index lags far behind, these can be
+//! very many prefixes. With a `Spill` configured, each operator writes its blocked records to
+//! files once it holds more than a threshold in memory, and reads them back one file at a time
+//! as they are processed. Files are removed once read, or when their records are dropped.
+//!
+//! Operators that fail to write or read back their records do not stop, but record the failure
+//! with their `Spill`, which reports it through `Spill::failed`.
+
+use std::fmt;
+use std::error::Error;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use abomonation::{Abomonation, encode, decode};
+
+// distinguishes the files of all operators in this process.
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Where and when operators should spill blocked records to disk.
+///
+/// Clones of a `Spill` share the record of any failure.
+#[derive(Clone, Debug)]
+pub struct Spill {
+    /// The directory in which to write spill files, which must exist.
+    pub directory: PathBuf,
+    /// The number of blocked records each operator may hold in memory.
+    pub threshold: usize,
+    failed: Rc<Cell<Option<SpillFailed>>>,
+}
+
+impl Spill {
+    /// Spill records past `threshold` per operator to files in `directory`.
+    pub fn new<P: Into<PathBuf>>(directory: P, threshold: usize) -> Self {
+        Spill { directory: directory.into(), threshold, failed: Rc::new(Cell::new(None)) }
+    }
+
+    /// Reports the first failure of any operator to write or read back spilled records.
+    ///
+    /// Records an operator failed to write remain in memory. Records it failed to read back are
+    /// lost, and the results of queries at their times are incomplete; the computation should 
+    /// be stopped.
+    pub fn failed(&self) -> Option<SpillFailed> {
+        self.failed.get()
+    }
+
+    // records a failure, unless one has already been recorded.
+    fn fail(&self, writing: bool, error: &io::Error) {
+        if self.failed.get().is_none() {
+            self.failed.set(Some(SpillFailed { writing, kind: error.kind() }));
+        }
+    }
+}
+
+/// The failure of an operator to write spilled records to disk or to read them back.
+#[derive(Copy, Clone, Debug)]
+pub struct SpillFailed {
+    /// Whether records were being written, rather than read back.
+    pub writing: bool,
+    /// The kind of the underlying I/O error.
+    pub kind: io::ErrorKind,
+}
+
+impl fmt::Display for SpillFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = if self.writing { "write" } else { "read back" };
+        write!(f, "failed to {} spilled records: {:?}", action, self.kind)
+    }
+}
+
+impl Error for SpillFailed { }
+
+/// A list of records, some of which may have been written to files.
+pub struct Spilled<D> {
+    /// Records held in memory.
+    pub memory: Vec<D>,
+    /// Files of records, and the number in each.
+    files: Vec<(PathBuf, usize)>,
+}
+
+impl<D> Default for Spilled<D> {
+    fn default() -> Self { Spilled { memory: Vec::new(), files: Vec::new() } }
+}
+
+impl<D: Abomonation+Clone> Spilled<D> {
+
+    /// The number of records, in memory and in files.
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.files.iter().map(|x| x.1).sum::<usize>()
+    }
+
+    /// Writes the records in memory to a new file in `directory`.
+    pub fn spill(&mut self, spill: &Spill) -> io::Result<()> {
+        if !self.memory.is_empty() {
+            let name = format!("spill-{}-{}.bin", ::std::process::id(), SPILL_FILES.fetch_add(1, Ordering::Relaxed));
+            let path = spill.directory.join(name);
+            let mut bytes = Vec::new();
+            unsafe { encode(&self.memory, &mut bytes)?; }
+            File::create(&path)?.write_all(&bytes)?;
+            self.files.push((path, self.memory.len()));
+            self.memory = Vec::new();
+        }
+        Ok(())
+    }
+
+    /// Reads the records of one file back into memory, and removes the file.
+    ///
+    /// Returns `false` if there are no files to read.
+    pub fn reload_one(&mut self) -> io::Result<bool> {
+        match self.files.pop() {
+            Some((path, count)) => {
+                let mut bytes = Vec::new();
+                File::open(&path)?.read_to_end(&mut bytes)?;
+                fs::remove_file(&path)?;
+                let records = unsafe { decode::<Vec<D>>(&mut bytes) }
+                    .map(|x| x.0)
+                    .filter(|x| x.len() == count)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt spill file"))?;
+                self.memory.extend(records.iter().cloned());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+// files not yet read back are removed with the records they hold.
+impl<D> Drop for Spilled<D> {
+    fn drop(&mut self) {
+        for (path, _) in self.files.drain(..) {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// Reads the records of one of `data`'s files back into memory, recording any failure with `spill`.
+///
+/// Returns `false` if there are no files to read, or if the file could not be read.
+pub fn reload<D: Abomonation+Clone>(data: &mut Spilled<D>, spill: &Option<Spill>) -> bool {
+    match data.reload_one() {
+        Ok(reloaded) => reloaded,
+        Err(error) => {
+            if let Some(ref spill) = *spill { spill.fail(false, &error); }
+            false
+        },
+    }
+}
+
+/// Spills the most recent records of `blocked` until at most `spill.threshold` remain in memory.
+///
+/// The records at the latest times are spilled first, as they will be the last to be processed.
+/// Records at times `working` indicates are being processed, and are never spilled. Once writing
+/// fails, the failure is recorded with `spill` and records remain in memory.
+pub fn spill_latest<C, D: Abomonation+Clone, F: Fn(&C)->bool>(blocked: &mut [(C, Spilled<D>)], spill: &Spill, working: F) {
+    let mut in_memory: usize = blocked.iter().map(|x| x.1.memory.len()).sum();
+    for &mut (ref time, ref mut data) in blocked.iter_mut().rev() {
+        if in_memory <= spill.threshold || spill.failed().is_some() { break; }
+        if working(time) { continue; }
+        in_memory -= data.memory.len();
+        if let Err(error) = data.spill(spill) {
+            spill.fail(true, &error);
+        }
+    }
+}
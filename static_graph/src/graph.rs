@@ -85,7 +85,7 @@ pub struct GraphExtender<G: GraphTrait, P, L: Fn(&P)->u64> {
 }
 
 impl<G: GraphTrait, P, L: Fn(&P)->u64+'static> PrefixExtender for GraphExtender<G, P, L>
-where <G as GraphTrait>::Target : Clone {
+where <G as GraphTrait>::Target : Clone+'static {
     type Prefix = P;
     type Extension = G::Target;
 
@@ -105,20 +105,14 @@ where <G as GraphTrait>::Target : Clone {
 
     fn intersect(&self, prefix: &P, list: &mut Vec<G::Target>) {
         let node = (*self.logic)(prefix) as usize;
-        let mut slice = self.graph.edges(node);
+        let slice = self.graph.edges(node);
 
-        if list.len() < slice.len() / 4 {
-            list.retain(|value| {
-                slice = gallop(slice, value);
-                slice.len() > 0 && &slice[0] == value
-            });
-        }
-        else {
-            list.retain(move |value| {
-                while slice.len() > 0 && &slice[0] < value { slice = &slice[1..]; }
-                slice.len() > 0 && &slice[0] == value
-            });
-        }
+        // mark the elements of `list` found in `slice`, then retain only those.
+        let mut found = vec![false; list.len()];
+        ::intersection::intersect_lanes(&list[..], slice, |index, _| found[index] = true);
+
+        let mut index = 0;
+        list.retain(|_| { index += 1; found[index - 1] });
     }
 }
 
@@ -3,6 +3,7 @@
 extern crate timely;
 extern crate time;
 extern crate mmap;
+extern crate intersection;
 
 use std::rc::Rc;
 